@@ -2,4 +2,5 @@
 
 cargo build --release
 sudo install -Dm755 ./target/release/tohorank /usr/bin/tohorank
-install -Dm644 ./src/touhous.txt $HOME/.tohorank/touhous.txt
\ No newline at end of file
+install -Dm644 ./src/touhous.txt $HOME/.tohorank/touhous.txt
+install -Dm644 ./src/tags.txt $HOME/.tohorank/tags.txt
\ No newline at end of file
@@ -1,6 +1,6 @@
 // Tohorank: characters functions
 
-use crate::{Chara, Glicko, Past, Match, data};
+use crate::{Chara, Glicko, Past, Match, Snapshot, data, stats};
 use std::collections::VecDeque;
 use chrono::Local;
 use fuzzy_matcher::FuzzyMatcher;
@@ -22,6 +22,7 @@ pub fn reset(chara: &mut Chara) {
             old_rate: VecDeque::with_capacity(7),
             peak_rate: None,
             peak_rank: None,
+            timeline: Vec::new(),
         },
         recent: VecDeque::with_capacity(7),
         ..chara.clone()
@@ -103,6 +104,23 @@ pub fn update_history(touhous: &mut Vec<Chara>, records: &Vec<Match>) {
     }
 }
 
+// Adds where everyone stands now to their timelines, call after the ratings are updated
+pub fn update_timeline(touhous: &mut Vec<Chara>) {
+    let everyone = stats::filter_group(vec![], touhous);
+    let ranks: Vec<usize> = touhous.iter()
+        .map(|th| stats::rank_in_group(th, &everyone).0)
+        .collect();
+    let today = Local::now().format("%d %b, %Y").to_string();
+    for (th, rank) in touhous.iter_mut().zip(ranks) {
+        th.hist.timeline.push(Snapshot {
+            rate: th.rank.rate,
+            devi: th.rank.devi,
+            rank,
+            time: today.clone(),
+        });
+    }
+}
+
 // Summons the mutable reference to the two characters at index1,2
 // Note: make sure the index are in bound
 pub fn summon<'a>(touhous: &'a mut Vec<&mut Chara>, index1: &usize, index2: &usize)
@@ -147,13 +165,3 @@ pub fn find_mut(touhous: &mut Vec<Chara>, query: String)
     }
     best_match
 }
-// Exact match
-pub fn find_mut_exact(touhous: &mut Vec<Chara>, query: String)
--> Option<&mut Chara> {
-    for th in touhous.iter_mut() {
-        if th.name == query {
-            return Some(th);
-        }
-    }
-    None
-}
\ No newline at end of file
@@ -0,0 +1,156 @@
+// Tohorank: terminal charts of a character's history
+
+use crate::Chara;
+use termplot::*;
+
+// chart size in pixels, a character is 2 by 4 pixels
+const WIDTH: usize = 120;
+const HEIGHT: usize = 48;
+
+// A polyline through the points
+struct Line(Vec<(f64, f64)>);
+
+impl DrawView for Line {
+    fn draw(&self, _: &View, canvas: &mut ViewCanvas) {
+        for pair in self.0.windows(2) {
+            canvas.line(pair[0].0, pair[0].1, pair[1].0, pair[1].1);
+        }
+    }
+}
+
+// Same as Line but dotted, for the uncertainty band
+// the second field is the gap between dots along the x-axis
+struct Dotted(Vec<(f64, f64)>, f64);
+
+impl DrawView for Dotted {
+    fn draw(&self, _: &View, canvas: &mut ViewCanvas) {
+        let step = self.1;
+        for pair in self.0.windows(2) {
+            let (x0, y0, x1, y1) = (pair[0].0, pair[0].1, pair[1].0, pair[1].1);
+            let mut x = x0;
+            while x < x1 {
+                canvas.point(x, y0 + (y1 - y0) * (x - x0) / (x1 - x0));
+                x += step;
+            }
+        }
+        if let Some(last) = self.0.last() {
+            canvas.point(last.0, last.1);
+        }
+    }
+}
+
+// A small cross, to mark the peaks
+// (x, y, half width, half height)
+struct Marker(f64, f64, f64, f64);
+
+impl DrawView for Marker {
+    fn draw(&self, _: &View, canvas: &mut ViewCanvas) {
+        let (dx, dy) = (self.2, self.3);
+        canvas.line(self.0 - dx, self.1 - dy, self.0 + dx, self.1 + dy);
+        canvas.line(self.0 - dx, self.1 + dy, self.0 + dx, self.1 - dy);
+    }
+}
+
+// Rating (with the ±1.96·RD band) and rank over every session, for `stat`
+pub fn show_history(chara: &Chara) {
+    let timeline = &chara.hist.timeline;
+    if timeline.len() < 2 {
+        println!("    ⓘ Charts appear after {} has been in 2 sessions.", chara.name);
+        return;
+    }
+    let last = (timeline.len() - 1) as f64;
+
+    // Rating
+    let rating: Vec<(f64, f64)> = timeline.iter().enumerate()
+        .map(|(n, a)| (n as f64, a.rate))
+        .collect();
+    let upper: Vec<(f64, f64)> = timeline.iter().enumerate()
+        .map(|(n, a)| (n as f64, a.rate + a.devi * 1.96))
+        .collect();
+    let lower: Vec<(f64, f64)> = timeline.iter().enumerate()
+        .map(|(n, a)| (n as f64, a.rate - a.devi * 1.96))
+        .collect();
+    let low = lower.iter().map(|a| a.1).fold(f64::INFINITY, f64::min);
+    let high = upper.iter().map(|a| a.1).fold(f64::NEG_INFINITY, f64::max);
+    let peak_rate = rating.iter().cloned().max_by(|a, b| a.1.partial_cmp(&b.1).unwrap()).unwrap();
+
+    // one pixel in chart units
+    let (px, py) = (last / WIDTH as f64, (high - low) / HEIGHT as f64);
+
+    let mut plot = Plot::default();
+    plot.set_domain(Domain(0.0..last))
+        .set_codomain(Domain(low.floor()..high.ceil()))
+        .set_title(&format!("Rating of {} ", chara.name))
+        .set_x_label("X-axis: Sessions")
+        .set_y_label(&format!("Y-axis: Rating ± 1.96·RD, x marks the peak ({:.0})", peak_rate.1))
+        .set_size(Size::new(WIDTH, HEIGHT))
+        .add_plot(Box::new(Dotted(upper, px * 3.0)))
+        .add_plot(Box::new(Dotted(lower, px * 3.0)))
+        .add_plot(Box::new(Line(rating)))
+        .add_plot(Box::new(Marker(peak_rate.0, peak_rate.1, px * 2.0, py * 2.0)));
+    println!("{plot}");
+
+    // Rank
+    let rank: Vec<(f64, f64)> = timeline.iter().enumerate()
+        .map(|(n, a)| (n as f64, a.rank as f64))
+        .collect();
+    let worst = rank.iter().map(|a| a.1).fold(1.0, f64::max);
+    let best = rank.iter().cloned().min_by(|a, b| a.1.partial_cmp(&b.1).unwrap()).unwrap();
+
+    let mut plot = Plot::default();
+    plot.set_domain(Domain(0.0..last))
+        .set_codomain(Domain(0.0..worst + 1.0))
+        .set_title(&format!("Rank of {} ", chara.name))
+        .set_x_label("X-axis: Sessions")
+        .set_y_label(&format!("Y-axis: Rank (lower is better), x marks the best (#{})", best.1))
+        .set_size(Size::new(WIDTH, HEIGHT * 2 / 3))
+        .add_plot(Box::new(Line(rank)))
+        .add_plot(Box::new(Marker(best.0, best.1, px * 2.0, (worst + 1.0) / (HEIGHT * 2 / 3) as f64 * 2.0)));
+    println!("{plot}");
+}
+
+// Bump chart of the top characters of the last ordering through every ordering
+// (names best first), with the path of each one below since the lines have no labels
+// only the characters in every ordering are ranked
+pub fn bump(columns: &[Vec<String>], top: usize) {
+    if columns.len() < 2 {
+        return;
+    }
+    let columns: Vec<Vec<&String>> = columns.iter()
+        .map(|list| list.iter().filter(|name| columns.iter().all(|other| other.contains(name))).collect())
+        .collect();
+    let last = columns.last().unwrap();
+    let shown: Vec<&String> = last.iter().take(top).cloned().collect();
+    if shown.is_empty() {
+        println!("    No one is in every ranking.\n");
+        return;
+    }
+    let paths: Vec<Vec<usize>> = shown.iter()
+        .map(|name| columns.iter().map(|list| list.iter().position(|a| a == name).unwrap() + 1).collect())
+        .collect();
+    let worst = paths.iter().flatten().max().cloned().unwrap_or(1) as f64;
+    let width = (columns.len() - 1) as f64;
+
+    // ranks go down, so they're drawn negative
+    let mut plot = Plot::default();
+    plot.set_domain(Domain(0.0..width))
+        .set_codomain(Domain(-worst - 1.0..0.0))
+        .set_title("Movements ")
+        .set_x_label("X-axis: First ranking to last")
+        .set_y_label(&format!("Y-axis: Rank (top is #1) of the top {} in the last", shown.len()))
+        .set_size(Size::new(WIDTH, HEIGHT));
+    for path in paths.iter() {
+        let points: Vec<(f64, f64)> = path.iter()
+            .enumerate()
+            .map(|(n, rank)| (n as f64, -(*rank as f64)))
+            .collect();
+        plot.add_plot(Box::new(Line(points)));
+    }
+    println!("{plot}");
+
+    for (name, path) in shown.iter().zip(paths.iter()) {
+        let steps: Vec<String> = path.iter().map(|rank| format!("#{}", rank)).collect();
+        println!("    {:<34}{}", name, steps.join(" → "));
+    }
+    println!();
+}
@@ -0,0 +1,218 @@
+// Tohorank: the user's own tags
+// Personal groups like "waifu" or "to-reconsider", kept in mytags.txt of the
+// profile, away from the dataset's tags so updates never touch them.
+// They work like any other tag: filters, `stat!` and `groups`.
+
+use crate::{Chara, chara, groups, meta, pack, sort};
+use crate::groups::{CUSTOM_BASE, TagInfo, Tags};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use colored::Colorize;
+
+fn custom_path(data_path: &Path)
+-> PathBuf {
+    data_path.with_file_name("mytags.txt")
+}
+
+// Read mytags.txt, lines are `key; name; member; member...`
+pub fn load(data_path: &Path) {
+    let Ok(text) = fs::read_to_string(custom_path(data_path)) else {
+        return;
+    };
+    let mut custom: Vec<(TagInfo, Vec<String>)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(';').map(|a| a.trim()).collect();
+        if fields.len() < 2 || fields[0].is_empty() {
+            println!("Skipping line {} of mytags.txt: expected `key; name; members...`", number + 1);
+            continue;
+        }
+        custom.push((
+            TagInfo {
+                id: CUSTOM_BASE + custom.len() as u32,
+                key: fields[0].to_string(),
+                category: "custom".to_string(),
+                name: fields[1].to_string(),
+                exname: String::new(),
+                aliases: Vec::new(),
+            },
+            fields[2..].iter().filter(|a| !a.is_empty()).map(|a| a.to_string()).collect(),
+        ));
+    }
+    groups::set_custom(custom);
+}
+
+// Write mytags.txt, tags nobody has are gone
+fn save(custom: Vec<(TagInfo, Vec<String>)>, data_path: &Path) {
+    let custom: Vec<(TagInfo, Vec<String>)> = custom.into_iter().filter(|a| !a.1.is_empty()).collect();
+    let mut text = String::from("# Your own tags: key; name; members...\n");
+    for (tag, members) in custom.iter() {
+        text.push_str(&format!("{}; {}; {}\n", tag.key, tag.name, members.join("; ")));
+    }
+    if let Err(e) = fs::write(custom_path(data_path), text) {
+        println!("Couldn't save your tags: {}", e);
+    }
+    // ids follow the order, give them out again
+    let custom = custom.into_iter()
+        .enumerate()
+        .map(|(n, (tag, members))| (TagInfo { id: CUSTOM_BASE + n as u32, ..tag }, members))
+        .collect();
+    groups::set_custom(custom);
+}
+
+// Follow renamed characters, `(old, new)` names
+pub fn rename(renamed: &[(String, String)], data_path: &Path) {
+    let mut custom = groups::custom();
+    if custom.is_empty() {
+        return;
+    }
+    for (_, members) in custom.iter_mut() {
+        for (old, new) in renamed.iter() {
+            if members.contains(old) {
+                members.retain(|a| a != old && a != new);
+                members.push(new.clone());
+            }
+        }
+    }
+    save(custom, data_path);
+}
+
+// A filter word: a tag, a flag or some metadata, maybe with a minus
+fn is_filter(token: &str)
+-> bool {
+    Tags::from_str(token.trim_start_matches('-')).is_ok() || pack::flag_rule(token).is_some() || meta::rule(token).is_some()
+}
+
+// Can this be the key of a new tag?
+fn check_key(key: &str)
+-> Result<(), String> {
+    if key.starts_with('-') || key.ends_with('!') || key.parse::<usize>().is_ok() || key.contains(';') || key.contains('=') {
+        Err(format!("\"{}\" can't be a tag", key))
+    } else if pack::flag(key).is_some() {
+        Err(format!("\"{}\" is a flag", key))
+    } else {
+        match Tags::from_str(key) {
+            Ok(tag) if !tag.is_custom() => Err(format!("\"{}\" is a tag of the dataset", key)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn show(custom: &[(TagInfo, Vec<String>)]) {
+    println!("==> {}", "MY TAGS".bold());
+    if custom.is_empty() {
+        println!("    None yet.");
+    }
+    for (tag, members) in custom.iter() {
+        println!("    {:<16} {:>4} {}", tag.key.bold(), members.len(), pack::get().nouns());
+    }
+    println!("\nⓘ `tag add [character] [tag]` and `tag remove [character] [tag]` for one {},", pack::get().noun);
+    println!("  `tag add [tag] where [filter]` and `tag remove [tag] where [filter]` for everyone in a filter,");
+    println!("  `tag remove [tag]` removes the tag.\n");
+}
+
+// The `tag` command
+pub fn command(line: &str, touhous: &mut Vec<Chara>, data_path: &Path) {
+    let mut custom = groups::custom();
+    let words: Vec<&str> = line.split_whitespace().skip(1).collect();
+    let adding = match words.first() {
+        Some(&"add") => true,
+        Some(&"remove") | Some(&"rm") => false,
+        _ => {
+            show(&custom);
+            return;
+        },
+    };
+    let verb = words[0];
+    let words = &words[1..];
+    if words.is_empty() {
+        println!("Usage: tag {} [character] [tag], or tag {} [tag] where [filter]", verb, verb);
+        return;
+    }
+    if !adding && words.len() == 1 {
+        let before = custom.len();
+        custom.retain(|a| !a.0.key.eq_ignore_ascii_case(words[0]));
+        if custom.len() == before {
+            println!("No tag named \"{}\".", words[0]);
+        } else {
+            save(custom, data_path);
+            println!("Removed the tag {}.", words[0]);
+        }
+        return;
+    }
+
+    // one character, or everyone in a filter after `where`
+    let (key, names): (&str, Vec<String>) = if words.get(1) == Some(&"where") {
+        let filter = &words[2..];
+        if filter.is_empty() {
+            println!("Usage: tag {} [tag] where [filter]", verb);
+            return;
+        }
+        if let Some(word) = filter.iter().find(|a| !is_filter(a)) {
+            println!("\"{}\" is not a tag, flag or field to filter by.", word);
+            return;
+        }
+        let (pool, _) = sort::bouncer(filter.join(" "), touhous);
+        (words[0], pool.iter().map(|th| th.name.clone()).collect())
+    } else {
+        let query = words[..words.len() - 1].join(" ");
+        let found = touhous.iter().find(|th| th.name.eq_ignore_ascii_case(&query))
+            .or_else(|| chara::find(touhous, query.clone()));
+        match found {
+            Some(th) => (words[words.len() - 1], vec![th.name.clone()]),
+            None => {
+                println!("Character \"{}\" not found!", query);
+                return;
+            },
+        }
+    };
+    if let Err(e) = check_key(key) {
+        println!("{}", e);
+        return;
+    }
+
+    let index = match custom.iter().position(|a| a.0.key.eq_ignore_ascii_case(key)) {
+        Some(index) => index,
+        None if adding => {
+            custom.push((
+                TagInfo {
+                    id: CUSTOM_BASE + custom.len() as u32,
+                    key: key.to_string(),
+                    category: "custom".to_string(),
+                    name: key.to_string(),
+                    exname: String::new(),
+                    aliases: Vec::new(),
+                },
+                Vec::new(),
+            ));
+            custom.len() - 1
+        },
+        None => {
+            println!("No tag named \"{}\".", key);
+            return;
+        },
+    };
+    let members = &mut custom[index].1;
+    let before = members.len();
+    if adding {
+        for name in names.iter() {
+            if !members.contains(name) {
+                members.push(name.clone());
+            }
+        }
+    } else {
+        members.retain(|a| !names.contains(a));
+    }
+    let changed = before.abs_diff(members.len());
+    match (names.len(), adding) {
+        (1, true) if changed == 1 => { println!("{} is now {}.", names[0].bold(), key.bold()); },
+        (1, false) if changed == 1 => { println!("{} is no longer {}.", names[0].bold(), key.bold()); },
+        (_, true) => { println!("Tagged {} more {} with {}.", changed, pack::get().nouns(), key.bold()); },
+        (_, false) => { println!("Untagged {} {} from {}.", changed, pack::get().nouns(), key.bold()); },
+    }
+    save(custom, data_path);
+}
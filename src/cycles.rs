@@ -0,0 +1,235 @@
+// Tohorank: contradictory picks
+// Builds a preference graph from the match log to find cycles (A > B > C > A)
+// and the results the current ratings disagree with the most.
+
+use crate::{Chara, FightCond, Match, Session, chara, glicko, sort};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+use colored::Colorize;
+
+// longest cycle we bother looking for
+const MAX_CYCLE_LEN: usize = 5;
+// how many of each to show
+const SHOW_LEN: usize = 10;
+
+// Directed preference graph: winner -> losers
+// Battles are netted per pair, so A beating B twice and losing once is one edge A -> B
+pub fn build_graph(log: &[Session])
+-> HashMap<usize, HashSet<usize>> {
+    let mut net: HashMap<(usize, usize), isize> = HashMap::new();
+    for battle in log.iter().flat_map(|a| a.records.iter()) {
+        // key the pair by the smaller id first, count wins for it
+        let (key, sign) = if battle.one < battle.two {
+            ((battle.one, battle.two), 1)
+        } else {
+            ((battle.two, battle.one), -1)
+        };
+        if battle.res == 1.0 {
+            *net.entry(key).or_default() += sign;
+        } else if battle.res == 0.0 {
+            *net.entry(key).or_default() -= sign;
+        }
+    }
+    let mut graph: HashMap<usize, HashSet<usize>> = HashMap::new();
+    for ((a, b), score) in net {
+        if score > 0 {
+            graph.entry(a).or_default().insert(b);
+        } else if score < 0 {
+            graph.entry(b).or_default().insert(a);
+        }
+    }
+    graph
+}
+
+// Shortest path from start to goal following the preferences, up to max_len nodes
+fn shortest_path(graph: &HashMap<usize, HashSet<usize>>, start: usize, goal: usize, max_len: usize)
+-> Option<Vec<usize>> {
+    let mut parent: HashMap<usize, usize> = HashMap::new();
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::from([(start, 1)]);
+    let mut seen: HashSet<usize> = HashSet::from([start]);
+    while let Some((node, len)) = queue.pop_front() {
+        if node == goal {
+            let mut path = vec![goal];
+            while let Some(p) = parent.get(path.last().unwrap()) {
+                path.push(*p);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if len >= max_len {
+            continue;
+        }
+        for next in graph.get(&node).into_iter().flatten() {
+            if seen.insert(*next) {
+                parent.insert(*next, node);
+                queue.push_back((*next, len + 1));
+            }
+        }
+    }
+    None
+}
+
+// Finds the shortest cycle through every edge, each cycle is reported once
+pub fn find_cycles(graph: &HashMap<usize, HashSet<usize>>)
+-> Vec<Vec<usize>> {
+    let mut cycles: Vec<Vec<usize>> = Vec::new();
+    let mut found: HashSet<Vec<usize>> = HashSet::new();
+    for (winner, losers) in graph.iter() {
+        for loser in losers.iter() {
+            // loser -> ... -> winner closes the loop
+            if let Some(mut cycle) = shortest_path(graph, *loser, *winner, MAX_CYCLE_LEN - 1) {
+                cycle.insert(0, *winner);
+                cycle.pop();
+                // rotate so the smallest id comes first, to spot duplicates
+                let first = cycle.iter().enumerate().min_by_key(|a| a.1).unwrap().0;
+                cycle.rotate_left(first);
+                if found.insert(cycle.clone()) {
+                    cycles.push(cycle);
+                }
+            }
+        }
+    }
+    cycles.sort_by_key(|a| a.len());
+    cycles
+}
+
+// Decisive results the current ratings disagree with, most violated first
+// Returns (expected score of the winner now, winner, loser)
+pub fn violations(touhous: &[Chara], log: &[Session])
+-> Vec<(f64, usize, usize)> {
+    let mut seen: HashSet<(usize, usize)> = HashSet::new();
+    let mut result: Vec<(f64, usize, usize)> = Vec::new();
+    // latest sessions first, so the newest pick of a pair is the one that counts
+    for battle in log.iter().rev().flat_map(|a| a.records.iter().rev()) {
+        if battle.res != 1.0 && battle.res != 0.0 {
+            continue;
+        }
+        if !seen.insert((battle.one.min(battle.two), battle.one.max(battle.two))) {
+            continue;
+        }
+        let (winner, loser) = if battle.res == 1.0 { (battle.one, battle.two) } else { (battle.two, battle.one) };
+        if touhous[winner].dont_know() || touhous[loser].dont_know() {
+            continue;
+        }
+        let (mut mu, mut phi) = (touhous[winner].rank.rate, touhous[winner].rank.devi);
+        let (mut muj, mut phij) = (touhous[loser].rank.rate, touhous[loser].rank.devi);
+        glicko::glicko_two_scale(&mut mu, &mut phi);
+        glicko::glicko_two_scale(&mut muj, &mut phij);
+        let expected = glicko::e(&mu, &muj, &phij);
+        if expected < 0.5 {
+            result.push((expected, winner, loser));
+        }
+    }
+    result.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    result
+}
+
+// The `inconsistencies` screen, offers a re-battle over the pairs found
+pub fn show(touhous: &mut Vec<Chara>, data_path: &Path, log: &[Session]) {
+    let graph = build_graph(log);
+    let cycles: Vec<Vec<usize>> = find_cycles(&graph).into_iter()
+        .filter(|a| a.iter().all(|id| !touhous[*id].dont_know()))
+        .collect();
+    let violated = violations(touhous, log);
+    // pairs to re-battle, in order of appearance
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+
+    println!("==> {}", "CYCLES".bold());
+    if cycles.is_empty() {
+        println!("    No contradictions found, what a consistent taste!");
+    }
+    for cycle in cycles.iter().take(SHOW_LEN) {
+        let names: Vec<&str> = cycle.iter()
+            .chain(cycle.first())
+            .map(|id| touhous[*id].name.as_str())
+            .collect();
+        println!("    {}", names.join(" > "));
+        for (n, id) in cycle.iter().enumerate() {
+            let pair = (*id, cycle[(n + 1) % cycle.len()]);
+            if !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+    }
+    if cycles.len() > SHOW_LEN {
+        println!("    ... and {} more.", cycles.len() - SHOW_LEN);
+    }
+
+    println!("\n==> {}", "MOST VIOLATED".bold());
+    if violated.is_empty() {
+        println!("    The ratings agree with every pick.");
+    }
+    for (expected, winner, loser) in violated.iter().take(SHOW_LEN) {
+        println!("    {} ({:.0}) over {} ({:.0}), {:.0}% expected now",
+            touhous[*winner].name,
+            touhous[*winner].rank.rate,
+            touhous[*loser].name,
+            touhous[*loser].rank.rate,
+            expected * 100.0
+        );
+        if !pairs.contains(&(*winner, *loser)) {
+            pairs.push((*winner, *loser));
+        }
+    }
+    println!();
+
+    if pairs.is_empty() {
+        return;
+    }
+    print!("Re-battle these {} pairs? [y/N] >> ", pairs.len());
+    let _ = io::stdout().flush();
+    let mut choice = String::default();
+    let _ = io::stdin().read_line(&mut choice);
+    if choice.trim().eq_ignore_ascii_case("y") {
+        rebattle(touhous, pairs, data_path);
+    }
+}
+
+// A session over exactly the given pairs (global IDs), in order
+pub fn rebattle(touhous: &mut Vec<Chara>, mut pairs: Vec<(usize, usize)>, data_path: &Path) {
+    println!("{}",
+        format!("=== Starting a focused session with {} pairs... ===", pairs.len()).blue()
+    );
+    let started = Instant::now();
+    let roster: HashMap<usize, String> = touhous.iter()
+        .enumerate()
+        .map(|(id, th)| (id, th.name.clone()))
+        .collect();
+    let mut records: Vec<Match> = Vec::new();
+    let mut next = 0;
+    {
+        let mut everyone: Vec<&mut Chara> = touhous.iter_mut().collect();
+        while next < pairs.len() {
+            let (a, b) = pairs[next];
+            let (one, two) = chara::summon(&mut everyone, &a, &b);
+            match sort::fight(&mut records, one, two, a, b) {
+                FightCond::Next => { next += 1; },
+                FightCond::Skip => { next += 1; },
+                FightCond::Undo(steps) => {
+                    // replay from the earliest undone pair
+                    let replay = records.len() - steps;
+                    let undone = (records[replay].one, records[replay].two);
+                    records.truncate(replay);
+                    if let Some(pos) = pairs.iter().position(|p| *p == undone) {
+                        next = pos;
+                    }
+                },
+                FightCond::Review => { sort::review(&mut records, &roster); },
+                FightCond::Live => { println!("The live view is not available here."); },
+                FightCond::Forget(id) => {
+                    // drop the rest of their pairs
+                    let kept: Vec<(usize, usize)> = pairs[next..].iter()
+                        .filter(|p| p.0 != id && p.1 != id)
+                        .cloned()
+                        .collect();
+                    pairs.truncate(next);
+                    pairs.extend(kept);
+                },
+                FightCond::Last => { break; },
+            }
+        }
+    }
+    sort::tally(touhous, &mut records, started, data_path);
+}
@@ -1,51 +1,140 @@
 // Tohorank: interface with the data file
 
-use crate::{chara, Chara, Glicko, Past, groups::Tags};
-use std::collections::HashSet;
+use crate::{custom, groups, lint, meta, pack, Chara, Glicko, Match, Past, Reference, Session, groups::Tags};
+use std::collections::{BTreeMap, HashSet};
 use std::str::FromStr;
 use std::collections::VecDeque;
-use std::fs::File;
+use std::fs::{self, File};
 use std::time::SystemTime;
-use std::io::{Write, BufRead, BufReader, BufWriter};
+use std::io::{self, Write, BufReader, BufWriter};
+use colored::Colorize;
 use std::process;
-use std::path::PathBuf;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
 
 pub static MAX_HISTORY_SESS: usize = 7;
 
-// Reads a line from the stock list and give a character
-pub fn chara_from_string(line: String)
--> Chara {
-    // fields that depend on the list
-    let mut chara_name: String = String::from("");
-    let mut chara_groups: HashSet<Tags> = HashSet::new();
-    let mut chara_flags: [bool; 4] = [false; 4];
+// the stock list of the Touhou pack, for when there's none on disk
+const STOCK: &str = include_str!("touhous.txt");
+
+// Deserialize a whole file, the size limit makes a file with the wrong layout
+// fail quickly instead of asking for an absurd amount of memory
+pub fn decode<T: DeserializeOwned>(file: File)
+-> bincode::Result<T> {
+    let limit = file.metadata().map(|a| a.len()).unwrap_or(0);
+    // same encoding as bincode::serialize()
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(BufReader::new(file))
+}
+
+// A character as the list has them, tags and flags by key
+pub struct Listed {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub flags: Vec<String>,
+    pub meta: Vec<(String, String)>,    // `key=value` fields
+}
+
+// Reads a line from the stock list, unknown tags and flags are left out (`lint` tells about them)
+fn listed_from_string(line: &str)
+-> Result<Listed, String> {
+    let mut listed = Listed {
+        name: String::new(),
+        tags: Vec::new(),
+        flags: Vec::new(),
+        meta: Vec::new(),
+    };
     for (part, data) in line.split("; ").enumerate() {
         if part == 0 {
             // name
-            chara_name = data.to_string();
+            if data.trim().is_empty() {
+                return Err("no name, expected `name; tags; flags`".to_string());
+            }
+            if data.contains(';') {
+                return Err(format!("\"{}\" is not a name, the fields are separated by \"; \"", data));
+            }
+            listed.name = data.trim().to_string();
         } else if part == 1 {
             // groups
-            for group in data.split(" ") {
-                chara_groups.insert(Tags::from_str(group).unwrap());
+            listed.tags.extend(data.split(" ").filter_map(|a| Tags::from_str(a).ok()).map(|a| a.key()));
+        } else if let Some((key, value)) = meta::parse(data) {
+            // metadata
+            if meta::title(&key).is_some() && !value.is_empty() {
+                listed.meta.push((key, value));
             }
-        } else {
+        } else if let Some(flag) = pack::flag_exact(data) {
             // flags
-            match data {
-                "pc98" => {
-                    chara_flags[0] = true;
-                },
-                "nameless" => {
-                    chara_flags[1] = true;
-                },
-                "notgirl" => {
-                    chara_flags[2] = true;
-                },
-                _ => { println!("???: Unknown flag.") },
-            }
+            listed.flags.push(flag.key);
+        }
+    }
+    Ok(listed)
+}
+
+// Reads a character list, after the `#` comments
+// a byte order mark is only a lint warning, it isn't part of the first line
+pub fn parse_list(text: &str)
+-> Result<Vec<Listed>, String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut listed: Vec<Listed> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
         }
+        listed.push(listed_from_string(line).map_err(|e| format!("line {}: {}", number + 1, e))?);
     }
+    Ok(listed)
+}
+
+// Read the pack's character list, the stock pack can do without one
+// it's checked first, and a list with errors isn't read at all
+fn read_list()
+-> Result<Vec<Listed>, String> {
+    let path = pack::get().list_path();
+    let (bytes, source) = match fs::read(&path) {
+        Ok(bytes) => (bytes, path.display().to_string()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && pack::get().stock => {
+            println!("No character list at {}, using the stock one. (`init` writes it out)", path.display());
+            (STOCK.as_bytes().to_vec(), "the stock list".to_string())
+        },
+        Err(e) => { return Err(format!("{}: {}", path.display(), e)); },
+    };
+    let problems = lint::check(&bytes);
+    lint::show(&problems, &source, false);
+    if lint::has_errors(&problems) {
+        return Err(format!("{} has errors, see above. (`lint` checks it again)", source));
+    }
+    let text = String::from_utf8(bytes).map_err(|e| format!("{}: {}", source, e))?;
+    parse_list(&text).map_err(|e| format!("{}, {}", source, e))
+}
+
+// The `init` command: write out the stock list and tags, to edit them
+pub fn init() {
+    let pack = pack::get();
+    if !pack.stock {
+        println!("This profile uses the pack \"{}\", `init` only writes the stock Touhou files.", pack.name);
+        return;
+    }
+    for (path, text) in [(pack.list_path(), STOCK), (pack.tags_path(), groups::STOCK)] {
+        if path.exists() {
+            println!("{} is already there, leaving it as it is.", path.display());
+            continue;
+        }
+        match fs::write(&path, text) {
+            Ok(_) => { println!("Wrote {}.", path.display()); },
+            Err(e) => { println!("Couldn't write {}: {}", path.display(), e); },
+        }
+    }
+}
+
+// A new character, with nothing but what the list says
+fn chara_from_listed(listed: &Listed)
+-> Chara {
     let touhou = Chara {
-        name: chara_name,
+        name: listed.name.clone(),
         rank: Glicko {
             rate: 1500.0,
             devi: 350.0,
@@ -59,111 +148,360 @@ pub fn chara_from_string(line: String)
             old_rank: VecDeque::with_capacity(MAX_HISTORY_SESS),
             peak_rate: None,
             peak_rank: None,
+            timeline: Vec::new(),
         },
         recent: VecDeque::with_capacity(MAX_HISTORY_SESS),
-        groups: chara_groups,
-        flags: chara_flags,
+        groups: listed.tags.iter().filter_map(|a| Tags::from_str(a).ok()).collect(),
+        flags: listed.flags.iter().cloned().collect(),
+        hidden: false,
+        retired: false,
+        meta: listed.meta.iter().cloned().collect(),
     };
     touhou
 }
 
-// Generate the data file from a stock list of characters (~/.tohorank/touhous.txt)
+// Generate the data file from the pack's list of characters (~/.tohorank/touhous.txt for Touhou)
 pub fn generate_data(data_path: &PathBuf) {
     let start = SystemTime::now();
-    // character array
-    let mut characters: Vec<Chara> = Vec::with_capacity(170);
-
-    // read from touhous.txt
-    let mut touhous_path = data_path.clone();
-    touhous_path.pop();
-    touhous_path.push("touhous.txt");
-
-    let mut err = false;
-    let file = File::open(&touhous_path).unwrap();
-    let reader = BufReader::new(file);
-    for (number, line) in reader.lines().skip(3).enumerate() {
-        match line {
-            Ok(l) => {
-                let touhou = chara_from_string(l);
-                println!("#{}: {}", number + 1, touhou.name);
-                characters.push(touhou);
-            }
-            Err(error) => {
-                eprintln!("\nError reading... {}", error);
-                err = true;
-                break;
+    let listed = match read_list() {
+        Ok(listed) => listed,
+        Err(e) => {
+            println!("Data file generation FAILED! Can't read the {} list:", pack::get().noun);
+            println!("    {}", e);
+            process::exit(1);
+        },
+    };
+    let mut characters: Vec<Chara> = Vec::with_capacity(listed.len());
+    for (number, entry) in listed.iter().enumerate() {
+        let touhou = chara_from_listed(entry);
+        println!("#{}: {}", number + 1, touhou.name);
+        characters.push(touhou);
+    }
+    write_data(&characters, data_path);
+    println!("==> Data file generation completed.");
+    println!("    got {} characters in {} µs.",
+        characters.len(),
+        start.elapsed().unwrap().as_micros()
+    );
+}
+
+// The renames the pack knows about, lines of renames.txt are `Old Name -> New Name`
+fn read_renames()
+-> Vec<(String, String)> {
+    let path = pack::get().dir.join("renames.txt");
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+    let mut renames: Vec<(String, String)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once("->").map(|(a, b)| (a.trim(), b.trim())) {
+            Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+                renames.push((old.to_string(), new.to_string()));
+            },
+            _ => { println!("Skipping line {} of {}: expected `Old Name -> New Name`", number + 1, path.display()); },
+        }
+    }
+    renames
+}
+
+// Fold the character at `gone` into the one at `keep`, who takes their name
+// `gone` leaves the Vec, so every global ID after it moves down one
+fn merge(touhous: &mut Vec<Chara>, log: &mut [Session], keep: usize, gone: usize) {
+    let recent = merged_recent(&touhous[keep], &touhous[gone], log, keep, gone);
+    let between: Vec<f32> = log.iter()
+        .flat_map(|session| session.records.iter())
+        .filter(|a| (a.one, a.two) == (keep, gone) || (a.one, a.two) == (gone, keep))
+        .map(|a| a.res)
+        .collect();
+    let other = touhous.remove(gone);
+    let keep = if keep > gone { keep - 1 } else { keep };
+    let th = &mut touhous[keep];
+    th.name = other.name;
+    th.retired = false;
+    if other.hist.wins + other.hist.loss + other.hist.draw > 0 {
+        // two guesses of the same rating, the surer one weighs more
+        let (ours, theirs) = (th.rank.devi.powi(-2), other.rank.devi.powi(-2));
+        th.rank.rate = (th.rank.rate * ours + other.rank.rate * theirs) / (ours + theirs);
+        th.rank.devi = (ours + theirs).powf(-0.5);
+        th.hist.wins += other.hist.wins;
+        th.hist.loss += other.hist.loss;
+        th.hist.draw += other.hist.draw;
+        // the battles against each other leave the log below, so they leave the totals
+        for res in between {
+            if res == 2.0 {
+                th.hist.loss = th.hist.loss.saturating_sub(2);
+            } else if res == 0.5 {
+                th.hist.draw = th.hist.draw.saturating_sub(2);
+            } else {
+                th.hist.wins = th.hist.wins.saturating_sub(1);
+                th.hist.loss = th.hist.loss.saturating_sub(1);
             }
         }
+        th.recent = recent;
+        merge_past(&mut th.hist, other.hist);
     }
-    write_data(&characters, data_path);
-    if err {
-        println!("Data file generation INCOMPLETE! Something's gone wrong.");
-        process::exit(1);
+    let id = |n: usize| if n == gone { keep } else if n > gone { n - 1 } else { n };
+    let fix = |battles: &mut Vec<Match>| {
+        for battle in battles.iter_mut() {
+            battle.one = id(battle.one);
+            battle.two = id(battle.two);
+        }
+        // they fought each other, that's gone too
+        battles.retain(|a| a.one != a.two);
+    };
+    for th in touhous.iter_mut() {
+        let mut recent: Vec<Match> = th.recent.drain(..).collect();
+        fix(&mut recent);
+        th.recent = recent.into();
+    }
+    for session in log.iter_mut() {
+        fix(&mut session.records);
+    }
+}
+
+// The recent battles of two characters together, newest first
+// the log knows the order, battles from before the log come last
+fn merged_recent(one: &Chara, two: &Chara, log: &[Session], id1: usize, id2: usize)
+-> VecDeque<Match> {
+    let involved = |a: &Match| [a.one, a.two].iter().any(|n| *n == id1 || *n == id2);
+    let logged: Vec<Match> = log.iter()
+        .rev()
+        .flat_map(|session| session.records.iter().rev())
+        .filter(|a| involved(a))
+        .cloned()
+        .collect();
+    let count = |id: usize| logged.iter().filter(|a| a.one == id || a.two == id).count();
+    let (logged1, logged2) = (count(id1), count(id2));
+    logged.into_iter()
+        .chain(one.recent.iter().skip(logged1).cloned())
+        .chain(two.recent.iter().skip(logged2).cloned())
+        .filter(|a| (a.one, a.two) != (id1, id2) && (a.one, a.two) != (id2, id1))  // against each other
+        .take(MAX_HISTORY_SESS)
+        .collect()
+}
+
+// Fold the history of `other` into `hist`: the best peaks, and the sessions side by side
+fn merge_past(hist: &mut Past, other: Past) {
+    if other.peak_rate.as_ref().is_some_and(|a| hist.peak_rate.as_ref().is_none_or(|b| a.0 > b.0)) {
+        hist.peak_rate = other.peak_rate;
+    }
+    if other.peak_rank.as_ref().is_some_and(|a| hist.peak_rank.as_ref().is_none_or(|b| a.0 < b.0)) {
+        hist.peak_rank = other.peak_rank;
+    }
+    // both are newest first, one entry a session: keep the better placed of the two
+    let sessions = hist.old_rank.len().max(other.old_rank.len());
+    let mut old: Vec<(f64, usize)> = Vec::with_capacity(sessions);
+    for n in 0..sessions {
+        let ours = hist.old_rate.get(n).zip(hist.old_rank.get(n));
+        let theirs = other.old_rate.get(n).zip(other.old_rank.get(n));
+        let better = match (ours, theirs) {
+            (Some(a), Some(b)) => if b.1 < a.1 { b } else { a },
+            (a, b) => a.or(b).unwrap(),
+        };
+        old.push((*better.0, *better.1));
+    }
+    hist.old_rate = old.iter().map(|a| a.0).collect();
+    hist.old_rank = old.iter().map(|a| a.1).collect();
+    // everyone gets a snapshot every session, so the timelines line up from the end
+    let (mut longer, shorter) = if other.timeline.len() > hist.timeline.len() {
+        (other.timeline, std::mem::take(&mut hist.timeline))
     } else {
-        println!("==> Data file generation completed.");
-        println!("    got {} characters in {} µs.",
-            characters.len(),
-            start.elapsed().unwrap().as_micros()
-        );
-    }
-}
-
-// Update the data file to add (not remove!) new characters and flags
-pub fn update_data(touhous: &mut Vec<Chara>, data_path: &PathBuf) {
-    let mut touhous_path = data_path.clone();
-    touhous_path.pop();
-    touhous_path.push("touhous.txt");
-
-    let file = File::open(&touhous_path).unwrap();
-    let reader = BufReader::new(file);
-    let (mut updated, mut added) = (0, 0);
-    for (_, line) in reader.lines().skip(3).enumerate() {
-        match line {
-            Ok(l) => {
-                if l.is_empty() {
-                    continue;
-                }
-                let tokens: Vec<&str> = l.split("; ").collect();
-                let name = tokens.get(0).unwrap();
-                if let Some(th) = chara::find_mut_exact(touhous, name.to_string()) {
-                    // update
-                    updated += 1;
-                    (th.flags[0], th.flags[1], th.flags[2]) = (false, false, false);
-                    for (part, data) in tokens.iter().enumerate() {
-                        if part == 1 {
-                            for tag in data.split(" ") {
-                                th.groups.insert(Tags::from_str(tag).unwrap());
-                            }
-                        } else if part > 1 {
-                            match *data {
-                                "pc98" => {
-                                    th.flags[0] = true;
-                                },
-                                "nameless" => {
-                                    th.flags[1] = true;
-                                },
-                                "notgirl" => {
-                                    th.flags[2] = true;
-                                },
-                                _ => { println!("???: Unknown flag.") },
-                            }
-                        }
-                    }
+        (std::mem::take(&mut hist.timeline), other.timeline)
+    };
+    let offset = longer.len() - shorter.len();
+    for (snap, theirs) in longer[offset..].iter_mut().zip(shorter) {
+        let (ours, weight) = (snap.devi.powi(-2), theirs.devi.powi(-2));
+        snap.rate = (snap.rate * ours + theirs.rate * weight) / (ours + weight);
+        snap.devi = (ours + weight).powf(-0.5);
+        snap.rank = snap.rank.min(theirs.rank);
+    }
+    hist.timeline = longer;
+}
+
+// Words of a name worth comparing, for rename suggestions
+fn name_words(name: &str)
+-> HashSet<String> {
+    name.split(|a: char| !a.is_alphanumeric())
+        .filter(|a| a.chars().count() >= 3)
+        .map(|a| a.to_lowercase())
+        .collect()
+}
+
+// An update worked out on a copy, nothing is saved until commit()
+pub struct Update {
+    touhous: Vec<Chara>,
+    log: Option<Vec<Session>>,      // only read when someone is merged
+    renamed: Vec<(String, String)>,
+    report: Vec<String>,
+    suggestions: Vec<(String, String)>,
+}
+
+// Work out what the list changes
+pub fn plan_update(touhous: &[Chara], listed: &[Listed], data_path: &Path)
+-> Update {
+    let mut update = Update {
+        touhous: touhous.to_vec(),
+        log: None,
+        renamed: Vec::new(),
+        report: Vec::new(),
+        suggestions: Vec::new(),
+    };
+    let is_listed = |name: &str| listed.iter().any(|a| a.name == name);
+
+    // renames first, so the records follow the new name
+    for (old, new) in read_renames() {
+        if is_listed(&old) || !is_listed(&new) {
+            continue;
+        }
+        let Some(from) = update.touhous.iter().position(|th| th.name == old) else {
+            continue;   // done already
+        };
+        match update.touhous.iter().position(|th| th.name == new) {
+            Some(dup) => {
+                let battles = update.touhous[dup].hist.wins + update.touhous[dup].hist.loss + update.touhous[dup].hist.draw;
+                let log = update.log.get_or_insert_with(|| read_log(data_path));
+                merge(&mut update.touhous, log, from, dup);
+                if battles == 0 {
+                    update.report.push(format!("{} {} → {} (replacing the new entry, it had no battles)", "Renamed:".blue(), old, new));
                 } else {
-                    // create
-                    added += 1;
-                    let th = chara_from_string(l);
-                    touhous.push(th);
+                    update.report.push(format!("{} {} into {} (with the {} battles of {})", "Merged:".blue(), old, new, battles, new));
+                }
+            },
+            None => {
+                update.touhous[from].name = new.clone();
+                update.report.push(format!("{} {} → {}", "Renamed:".blue(), old, new));
+            },
+        }
+        update.renamed.push((old, new));
+    }
+
+    let mut added: Vec<String> = Vec::new();
+    for entry in listed.iter() {
+        let Some(th) = update.touhous.iter_mut().find(|th| th.name == entry.name) else {
+            update.touhous.push(chara_from_listed(entry));
+            update.report.push(format!("{} {}", "Added:".green(), entry.name));
+            added.push(entry.name.clone());
+            continue;
+        };
+        // the list has the final say on tags and flags
+        let tags: HashSet<Tags> = entry.tags.iter().filter_map(|a| Tags::from_str(a).ok()).collect();
+        let flags: HashSet<String> = entry.flags.iter().cloned().collect();
+        let facts: BTreeMap<String, String> = entry.meta.iter().cloned().collect();
+        let mut changes: Vec<String> = Vec::new();
+        changes.extend(tags.difference(&th.groups).map(|a| format!("+{}", a.key())));
+        changes.extend(th.groups.difference(&tags).map(|a| format!("-{}", a.key())));
+        changes.extend(flags.difference(&th.flags).map(|a| format!("+{}", a)));
+        changes.extend(th.flags.difference(&flags).map(|a| format!("-{}", a)));
+        changes.extend(facts.iter().filter(|a| th.meta.get(a.0) != Some(a.1)).map(|a| format!("{}={}", a.0, a.1)));
+        changes.extend(th.meta.keys().filter(|a| !facts.contains_key(*a)).map(|a| format!("-{}", a)));
+        if !changes.is_empty() {
+            changes.sort();
+            update.report.push(format!("{} {} ({})", "Retagged:".yellow(), th.name, changes.join(" ")));
+        }
+        th.groups = tags;
+        th.flags = flags;
+        th.meta = facts;
+        if th.retired {
+            th.retired = false;
+            update.report.push(format!("{} {}", "Back in the list:".green(), th.name));
+        }
+    }
+
+    // the ones that left keep their records but leave the rankings
+    for th in update.touhous.iter_mut().filter(|th| !th.retired && !is_listed(&th.name)) {
+        th.retired = true;
+        update.report.push(format!("{} {} (kept with their records, out of the rankings)", "Retired:".red(), th.name));
+        // maybe they only changed names
+        let words = name_words(&th.name);
+        let best = added.iter()
+            .map(|a| (a, name_words(a).intersection(&words).count()))
+            .filter(|a| a.1 > 0)
+            .max_by_key(|a| a.1);
+        if let Some((new, _)) = best {
+            update.suggestions.push((th.name.clone(), new.clone()));
+        }
+    }
+    update
+}
+
+impl Update {
+    pub fn is_empty(&self)
+    -> bool {
+        self.report.is_empty()
+    }
+
+    pub fn show(&self) {
+        for line in self.report.iter() {
+            println!("    {}", line);
+        }
+        for (old, new) in self.suggestions.iter() {
+            println!("    {} {} → {}?", "Maybe renamed:".cyan(), old, new);
+        }
+        if !self.suggestions.is_empty() {
+            println!("ⓘ If so, put `Old Name -> New Name` lines in {} and run this again:", pack::get().dir.join("renames.txt").display());
+            println!("  the ratings and history move to the new name instead of starting over.");
+        }
+    }
+
+    // Save the update, and the names in the log, your tags and the references
+    pub fn commit(self, touhous: &mut Vec<Chara>, data_path: &Path) {
+        *touhous = self.touhous;
+        write_data(touhous, &data_path.to_path_buf());
+        if let Some(log) = self.log {
+            write_log(&log, data_path);
+        }
+        if self.renamed.is_empty() {
+            return;
+        }
+        custom::rename(&self.renamed, data_path);
+        let mut references = read_references(data_path);
+        for reference in references.iter_mut() {
+            for (name, _) in reference.ranks.iter_mut() {
+                if let Some((_, new)) = self.renamed.iter().find(|a| a.0 == *name) {
+                    *name = new.clone();
                 }
-            }
-            Err(error) => {
-                eprintln!("\nError reading... {}", error);
-                break;
             }
         }
+        write_references(&references, data_path);
+    }
+}
+
+// Update the data file to follow the pack's list: add, rename, retag and retire characters
+// `ask` is false when there's nobody at the terminal to say YES, like for `serve`
+pub fn update_data(touhous: &mut Vec<Chara>, data_path: &Path, ask: bool) {
+    let listed = match read_list() {
+        Ok(listed) => listed,
+        Err(e) => {
+            println!("Not updated, can't read the {} list:", pack::get().noun);
+            println!("    {}", e);
+            return;
+        },
+    };
+    let update = plan_update(touhous, &listed, data_path);
+    if update.is_empty() {
+        println!("Update: {} {} in the list, nothing to change.", listed.len(), pack::get().nouns());
+        return;
+    }
+    println!("==> {}", "UPDATE".bold());
+    update.show();
+    if !ask {
+        println!("\nThe {} list changed, but the data file stays as it was. Run `update` in the lobby to apply it.", pack::get().noun);
+        return;
     }
-    write_data(touhous, data_path);
-    println!("Update: {} characters updated, {} characters added.", updated, added);
+    println!("\nThe {} list changed. Type 'YES' in uppercase to update the data file...", pack::get().noun);
+    let _ = io::stdout().flush();
+    let mut choice = String::default();
+    let _ = io::stdin().read_line(&mut choice);
+    if choice != "YES\n" {
+        println!("Not updated, the data file stays as it was.");
+        return;
+    }
+    update.commit(touhous, data_path);
+    println!("Update: done, {} {} in the list.", listed.len(), pack::get().nouns());
 }
 
 // Write to the data file
@@ -174,4 +512,171 @@ pub fn write_data(touhous: &Vec<Chara>, data_path: &PathBuf) {
     let data_file = File::create(data_path).unwrap();
     let mut writer = BufWriter::new(data_file);
     writer.write_all(&encoded).unwrap();
-}
\ No newline at end of file
+}
+
+// The match log lives next to the data file
+fn log_path(data_path: &Path)
+-> PathBuf {
+    data_path.with_file_name("matches.bin")
+}
+
+// Read every session in the match log, empty if there's none
+pub fn read_log(data_path: &Path)
+-> Vec<Session> {
+    match File::open(log_path(data_path)) {
+        Ok(file) => decode(file).unwrap_or_else(|_| {
+            println!("Match log not good! Ignoring it...");
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Add a finished session to the match log
+pub fn append_log(session: Session, data_path: &Path) {
+    let mut sessions = read_log(data_path);
+    sessions.push(session);
+    write_log(&sessions, data_path);
+}
+
+// Replace the match log
+fn write_log(sessions: &[Session], data_path: &Path) {
+    let encoded: Vec<u8> = bincode::serialize(sessions).unwrap();
+    let log_file = File::create(log_path(data_path)).unwrap();
+    let mut writer = BufWriter::new(log_file);
+    writer.write_all(&encoded).unwrap();
+}
+
+// The saved reference rankings, for `compare`
+fn references_path(data_path: &Path)
+-> PathBuf {
+    data_path.with_file_name("references.bin")
+}
+
+// Read the saved reference rankings, empty if there's none
+pub fn read_references(data_path: &Path)
+-> Vec<Reference> {
+    match File::open(references_path(data_path)) {
+        Ok(file) => decode(file).unwrap_or_else(|_| {
+            println!("Saved references not good! Ignoring them...");
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Replace the saved reference rankings
+pub fn write_references(references: &[Reference], data_path: &Path) {
+    let encoded: Vec<u8> = bincode::serialize(references).unwrap();
+    let file = File::create(references_path(data_path)).unwrap();
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&encoded).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listed(name: &str, tags: &[&str], flags: &[&str])
+    -> Listed {
+        Listed {
+            name: name.to_string(),
+            tags: tags.iter().map(|a| a.to_string()).collect(),
+            flags: flags.iter().map(|a| a.to_string()).collect(),
+            meta: Vec::new(),
+        }
+    }
+
+    fn battle(one: usize, two: usize, res: f32)
+    -> Match {
+        Match { one, two, res }
+    }
+
+    #[test]
+    fn reads_list_lines() {
+        crate::test_pack();
+        let entry = listed_from_string("Cirno; EoSD st2 bogus; pc98; species=Fairy; colour=blue").unwrap();
+        assert_eq!(entry.name, "Cirno");
+        assert_eq!(entry.tags.len(), 2);
+        assert_eq!(entry.flags, vec!["pc98"]);
+        assert_eq!(entry.meta, vec![("species".to_string(), "Fairy".to_string())]);
+        assert!(listed_from_string("; EoSD").is_err());
+        assert!(parse_list("# comment\n\nCirno; EoSD\n; st1\n").err().unwrap().starts_with("line 4"));
+    }
+
+    #[test]
+    fn skips_a_byte_order_mark() {
+        crate::test_pack();
+        let names = |text: &str| parse_list(text).unwrap().into_iter().map(|a| a.name).collect::<Vec<_>>();
+        assert_eq!(names("\u{feff}# The character list\nCirno; EoSD\n"), vec!["Cirno"]);
+        assert_eq!(names("\u{feff}Rumia; EoSD\r\nCirno; EoSD\r\n"), vec!["Rumia", "Cirno"]);
+    }
+
+    #[test]
+    fn plans_an_update() {
+        crate::test_pack();
+        let mut touhous: Vec<Chara> = ["Cirno", "Hong Meiling", "Rumia"].iter().map(|a| Chara::rated(a, 1500.0, 350.0)).collect();
+        touhous[0].groups.insert(Tags::from_str("EoSD").unwrap());
+        touhous[2].retired = true;
+        let list = vec![
+            listed("Cirno", &["EoSD", "st2"], &[]),
+            listed("Rumia", &["EoSD"], &[]),
+            listed("Hong Meirin", &["EoSD"], &[]),
+        ];
+        let update = plan_update(&touhous, &list, Path::new("/nonexistent/data.bin"));
+        assert_eq!(update.report, vec![
+            "Retagged: Cirno (+st2)".to_string(),
+            "Retagged: Rumia (+EoSD)".to_string(),
+            "Back in the list: Rumia".to_string(),
+            "Added: Hong Meirin".to_string(),
+            "Retired: Hong Meiling (kept with their records, out of the rankings)".to_string(),
+        ]);
+        assert_eq!(update.suggestions, vec![("Hong Meiling".to_string(), "Hong Meirin".to_string())]);
+        assert_eq!(update.touhous.len(), 4);
+        assert!(update.touhous[1].retired && !update.touhous[2].retired);
+        // nothing to do the second time
+        assert!(plan_update(&update.touhous, &list, Path::new("/nonexistent/data.bin")).is_empty());
+    }
+
+    #[test]
+    fn merges_two_entries() {
+        let mut touhous: Vec<Chara> = ["Old", "Other", "New"].iter().map(|a| Chara::rated(a, 1500.0, 100.0)).collect();
+        touhous[0].rank.rate = 1600.0;
+        touhous[2].rank.rate = 1400.0;
+        (touhous[0].hist.wins, touhous[0].hist.loss, touhous[0].hist.draw) = (1, 1, 1);
+        touhous[2].hist.wins = 1;
+        touhous[2].hist.loss = 1;
+        touhous[0].hist.peak_rate = Some((1650.0, "then".to_string()));
+        touhous[2].hist.peak_rate = Some((1700.0, "now".to_string()));
+        touhous[2].hist.peak_rank = Some((3, "now".to_string()));
+        // the old entry fought in the first session, the new one in the second
+        let mut log = vec![
+            Session { time: String::new(), secs: 0, records: vec![battle(0, 1, 1.0), battle(0, 1, 0.5)], summary: String::new() },
+            Session { time: String::new(), secs: 0, records: vec![battle(2, 1, 0.0), battle(2, 0, 1.0)], summary: String::new() },
+        ];
+        touhous[0].recent = vec![battle(2, 0, 1.0), battle(0, 1, 0.5), battle(0, 1, 1.0)].into();
+        touhous[2].recent = vec![battle(2, 0, 1.0), battle(2, 1, 0.0)].into();
+        let snapshot = |rate: f64, rank: usize| crate::Snapshot { rate, devi: 100.0, rank, time: String::new() };
+        touhous[0].hist.timeline = vec![snapshot(1600.0, 1), snapshot(1600.0, 1)];
+        touhous[2].hist.timeline = vec![snapshot(1400.0, 3)];
+
+        merge(&mut touhous, &mut log, 0, 2);
+        assert_eq!(touhous.len(), 2);
+        let th = &touhous[0];
+        assert_eq!(th.name, "New");
+        assert!((th.rank.rate - 1500.0).abs() < 1e-9);
+        assert!(th.rank.devi < 100.0);
+        // one win and one loss of the battle against each other are gone, like the battle
+        assert_eq!((th.hist.wins, th.hist.loss, th.hist.draw), (1, 1, 1));
+        // newest first, and not against each other
+        let recent: Vec<(usize, usize, f32)> = th.recent.iter().map(|a| (a.one, a.two, a.res)).collect();
+        assert_eq!(recent, vec![(0, 1, 0.0), (0, 1, 0.5), (0, 1, 1.0)]);
+        assert_eq!(th.hist.peak_rate.as_ref().unwrap().0, 1700.0);
+        assert_eq!(th.hist.peak_rank.as_ref().unwrap().0, 3);
+        let timeline: Vec<(f64, usize)> = th.hist.timeline.iter().map(|a| (a.rate.round(), a.rank)).collect();
+        assert_eq!(timeline, vec![(1600.0, 1), (1500.0, 1)]);
+        // the log follows the ids, the battle between the two is gone
+        let logged: Vec<(usize, usize)> = log.iter().flat_map(|a| a.records.iter()).map(|a| (a.one, a.two)).collect();
+        assert_eq!(logged, vec![(0, 1), (0, 1), (0, 1)]);
+    }
+}
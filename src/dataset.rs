@@ -0,0 +1,551 @@
+// Tohorank: reading tohosort/charasort's data.js
+// The dataset is a JavaScript file, but only object literals are in it,
+// so a small reader for those is enough. Importing writes the stock list
+// and the tag list, then updates the data file from them.
+
+use crate::{Chara, data, groups, pack};
+use crate::data::Listed;
+use crate::groups::TagInfo;
+use crate::pack::Flag;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use colored::Colorize;
+
+// A JavaScript value
+enum Value {
+    Str(String),
+    Bool(bool),
+    Other,                          // numbers, null, anything we don't need
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn get(&self, key: &str)
+    -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|a| a.0 == key).map(|a| &a.1),
+            _ => None,
+        }
+    }
+    fn str(&self, key: &str)
+    -> Option<String> {
+        match self.get(key) {
+            Some(Value::Str(s)) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Reads one value at a time out of the text
+struct Reader<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn line(&self)
+    -> usize {
+        self.text[..self.pos.min(self.text.len())].iter().filter(|a| **a == b'\n').count() + 1
+    }
+
+    fn error(&self, what: &str)
+    -> String {
+        format!("line {}: {}", self.line(), what)
+    }
+
+    // skip spaces and comments
+    fn skip(&mut self) {
+        while self.pos < self.text.len() {
+            let rest = &self.text[self.pos..];
+            if rest[0].is_ascii_whitespace() {
+                self.pos += 1;
+            } else if rest.starts_with(b"//") {
+                while self.pos < self.text.len() && self.text[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else if rest.starts_with(b"/*") {
+                self.pos = match rest.windows(2).position(|a| a == b"*/") {
+                    Some(end) => self.pos + end + 2,
+                    None => self.text.len(),
+                };
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn peek(&mut self)
+    -> Option<u8> {
+        self.skip();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn string(&mut self)
+    -> Result<String, String> {
+        let quote = self.text[self.pos];
+        self.pos += 1;
+        let mut bytes: Vec<u8> = Vec::new();
+        while let Some(&c) = self.text.get(self.pos) {
+            self.pos += 1;
+            if c == quote {
+                return String::from_utf8(bytes).map_err(|_| self.error("a string isn't UTF-8"));
+            } else if c == b'\\' {
+                let escaped = *self.text.get(self.pos).ok_or(self.error("the file ends in a string"))?;
+                self.pos += 1;
+                match escaped {
+                    b'n' => bytes.push(b'\n'),
+                    b't' => bytes.push(b'\t'),
+                    b'u' => {
+                        let code = std::str::from_utf8(self.text.get(self.pos..self.pos + 4).unwrap_or(b""))
+                            .ok()
+                            .and_then(|a| u32::from_str_radix(a, 16).ok())
+                            .and_then(char::from_u32)
+                            .ok_or(self.error("bad \\u escape"))?;
+                        self.pos += 4;
+                        bytes.extend(code.to_string().bytes());
+                    },
+                    other => bytes.push(other),
+                }
+            } else if c == b'\n' && quote != b'`' {
+                return Err(self.error("a string doesn't end"));
+            } else {
+                bytes.push(c);
+            }
+        }
+        Err(self.error("the file ends in a string"))
+    }
+
+    // a word: an unquoted key, true, false, a number...
+    fn word(&mut self)
+    -> String {
+        let start = self.pos;
+        while self.pos < self.text.len()
+            && (self.text[self.pos].is_ascii_alphanumeric() || b"_$.-+".contains(&self.text[self.pos])) {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.pos]).to_string()
+    }
+
+    fn expect(&mut self, c: u8)
+    -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c as char)))
+        }
+    }
+
+    fn value(&mut self)
+    -> Result<Value, String> {
+        match self.peek() {
+            None => Err(self.error("the file ends too early")),
+            Some(b'"') | Some(b'\'') | Some(b'`') => Ok(Value::Str(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut list = Vec::new();
+                loop {
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        break;
+                    }
+                    list.push(self.value()?);
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        self.expect(b']')?;
+                        break;
+                    }
+                }
+                Ok(Value::List(list))
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                loop {
+                    let key = match self.peek() {
+                        Some(b'}') => {
+                            self.pos += 1;
+                            break;
+                        },
+                        Some(b'"') | Some(b'\'') => self.string()?,
+                        _ => self.word(),
+                    };
+                    if key.is_empty() {
+                        return Err(self.error("expected a key"));
+                    }
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        self.expect(b'}')?;
+                        break;
+                    }
+                }
+                Ok(Value::Object(fields))
+            },
+            Some(_) => match self.word().as_str() {
+                "" => Err(self.error("unexpected character")),
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Ok(Value::Other),
+            },
+        }
+    }
+
+    // the value assigned after `pattern`, like `.characterData = [...]`
+    fn assigned(&mut self, pattern: &str)
+    -> Result<Value, String> {
+        let text = String::from_utf8_lossy(self.text);
+        let start = text.find(pattern).ok_or(format!("there's no {} in it", pattern.trim_start_matches('.')))?;
+        self.pos = start + pattern.len();
+        self.expect(b'=')?;
+        self.value()
+    }
+}
+
+// A character of the dataset
+pub struct Entry {
+    pub name: String,
+    pub tags: Vec<String>,      // keys
+    pub flags: Vec<String>,
+}
+
+// Everything in a data.js
+pub struct Dataset {
+    pub version: String,
+    pub tags: Vec<TagInfo>,     // ids not given yet
+    pub flags: Vec<Flag>,       // the options without a sub list
+    pub charas: Vec<Entry>,
+}
+
+// Read a tohosort/charasort data.js
+pub fn parse(text: &str)
+-> Result<Dataset, String> {
+    let mut reader = Reader { text: text.as_bytes(), pos: 0 };
+    let version = match reader.assigned("dataSetVersion") {
+        Ok(Value::Str(version)) => version,
+        _ => "unknown version".to_string(),
+    };
+
+    // options with a sub list are tags, the rest are flags
+    let mut tags: Vec<TagInfo> = Vec::new();
+    let mut flags: Vec<Flag> = Vec::new();
+    let Value::List(options) = reader.assigned(".options")? else {
+        return Err("the options aren't a list".to_string());
+    };
+    for option in options.iter() {
+        let Some(Value::List(sub)) = option.get("sub") else {
+            // like "Remove PC-98 Duplicates", the key is the flag
+            if let Some(key) = option.str("key") {
+                flags.push(Flag { name: option.str("name").unwrap_or(key.clone()), key, include: true, aliases: Vec::new() });
+            }
+            continue;
+        };
+        let category = match option.str("key").unwrap_or_default().as_str() {
+            "series" => "work".to_string(),
+            other => other.to_lowercase(),
+        };
+        for tag in sub.iter() {
+            let Some(key) = tag.str("key") else {
+                continue;
+            };
+            let exname = tag.str("tooltip").unwrap_or_default();
+            // "th06" for the numbered games
+            let number = exname.split(' ').next().unwrap_or("");
+            let aliases = if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                vec![format!("th{}", number)]
+            } else {
+                Vec::new()
+            };
+            tags.push(TagInfo {
+                id: 0,
+                name: tag.str("name").unwrap_or(key.clone()),
+                key,
+                category: category.clone(),
+                exname,
+                aliases,
+            });
+        }
+    }
+
+    let Value::List(list) = reader.assigned(".characterData")? else {
+        return Err("the characters aren't a list".to_string());
+    };
+    let mut charas: Vec<Entry> = Vec::with_capacity(list.len());
+    for chara in list.iter() {
+        let Some(name) = chara.str("name") else {
+            continue;
+        };
+        let mut entry = Entry { name, tags: Vec::new(), flags: Vec::new() };
+        if let Some(Value::Object(opts)) = chara.get("opts") {
+            for (key, value) in opts.iter() {
+                match value {
+                    Value::List(keys) => {
+                        for tag in keys.iter() {
+                            if let Value::Str(tag) = tag {
+                                entry.tags.push(tag.clone());
+                            }
+                        }
+                    },
+                    Value::Bool(true) => { entry.flags.push(key.clone()); },
+                    _ => {},
+                }
+            }
+        }
+        charas.push(entry);
+    }
+    Ok(Dataset { version, tags, flags, charas })
+}
+
+// The tags in use with the ones of the dataset: known keys keep their id
+// and take the new names, new ones get the next free ids
+pub fn merge_tags(current: &[TagInfo], new: &[TagInfo])
+-> (Vec<TagInfo>, Vec<String>) {
+    let mut result: Vec<TagInfo> = current.to_vec();
+    let mut added: Vec<String> = Vec::new();
+    let mut next = result.iter().map(|a| a.id + 1).max().unwrap_or(0);
+    for tag in new.iter() {
+        match result.iter_mut().find(|a| a.key.eq_ignore_ascii_case(&tag.key)) {
+            Some(known) => {
+                known.name = tag.name.clone();
+                known.exname = tag.exname.clone();
+                known.category = tag.category.clone();
+                for alias in tag.aliases.iter() {
+                    if !known.aliases.contains(alias) {
+                        known.aliases.push(alias.clone());
+                    }
+                }
+            },
+            None => {
+                result.push(TagInfo { id: next, ..tag.clone() });
+                added.push(tag.key.clone());
+                next += 1;
+            },
+        }
+    }
+    (result, added)
+}
+
+// A line of the stock list, `name; tags; flag; flag; key=value`
+fn stock_line(entry: &Entry, meta: &[(String, String)])
+-> String {
+    let mut line = format!("{}; {}", entry.name, entry.tags.join(" "));
+    for flag in entry.flags.iter() {
+        line.push_str("; ");
+        line.push_str(flag);
+    }
+    for (key, value) in meta.iter() {
+        line.push_str(&format!("; {}={}", key, value));
+    }
+    line
+}
+
+// The `import-dataset` command
+pub fn import(line: &str, touhous: &mut Vec<Chara>, data_path: &Path) {
+    let path = line.trim().split_once(' ').map(|a| a.1.trim()).unwrap_or("");
+    if path.is_empty() {
+        println!("Usage: import-dataset [data.js]");
+        println!("ⓘ The dataset file of tohosort or charasort, like 'src/js/data/2023-09-24.js'.\n");
+        return;
+    }
+    let dataset = match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| parse(&text)) {
+        Ok(dataset) => dataset,
+        Err(e) => {
+            println!("Couldn't read \"{}\": {}", path, e);
+            return;
+        },
+    };
+    let old_tags = groups::all();
+    let (tags, new_tags) = merge_tags(&old_tags, &dataset.tags);
+    let keys: HashMap<String, String> = tags.iter()
+        .flat_map(|a| std::iter::once(&a.key).chain(a.aliases.iter()).map(move |b| (b.to_lowercase(), a.key.clone())))
+        .collect();
+
+    // tags nobody knows are left out
+    let mut unknown: HashSet<String> = HashSet::new();
+    let listed: Vec<Listed> = dataset.charas.iter()
+        .map(|entry| Listed {
+            name: entry.name.clone(),
+            tags: entry.tags.iter()
+                .filter_map(|a| keys.get(&a.to_lowercase()).cloned().or_else(|| { unknown.insert(a.clone()); None }))
+                .collect(),
+            flags: entry.flags.clone(),
+            // the dataset has no metadata, what the list had stays
+            meta: touhous.iter()
+                .find(|th| th.name == entry.name)
+                .map(|th| th.meta.clone().into_iter().collect())
+                .unwrap_or_default(),
+        })
+        .collect();
+    // flags nobody declared yet, they come in included by default
+    let mut new_flags: Vec<Flag> = Vec::new();
+    for key in dataset.charas.iter().flat_map(|a| a.flags.iter()) {
+        if pack::flag_exact(key).is_none() && !new_flags.iter().any(|a| a.key == *key) {
+            new_flags.push(dataset.flags.iter().find(|a| a.key == *key).cloned().unwrap_or(Flag {
+                key: key.clone(),
+                name: key.clone(),
+                include: true,
+                aliases: Vec::new(),
+            }));
+        }
+    }
+
+    // what would change, worked out with the new tags and flags in place
+    let old_flags = pack::flags();
+    groups::set(tags.clone());
+    pack::set_flags(old_flags.iter().cloned().chain(new_flags.iter().cloned()).collect());
+    let update = data::plan_update(touhous, &listed, data_path);
+
+    println!("==> {}", format!("DATASET {}", dataset.version).bold());
+    println!("    {} characters, {} tags.", dataset.charas.len(), dataset.tags.len());
+    if !new_tags.is_empty() {
+        println!("    {} {}", "New tags:".blue(), new_tags.join(" "));
+    }
+    for flag in new_flags.iter() {
+        println!("    {} {} ({}, included by default)", "New flag:".blue(), flag.key, flag.name);
+    }
+    update.show();
+    if !unknown.is_empty() {
+        println!("    {} {}", "Unknown tags, ignored:".red(), unknown.into_iter().collect::<Vec<String>>().join(" "));
+    }
+    let restore = || {
+        groups::set(old_tags.clone());
+        pack::set_flags(old_flags.clone());
+    };
+    if update.is_empty() && new_tags.is_empty() && new_flags.is_empty() {
+        restore();
+        println!("\nNothing to change.\n");
+        return;
+    }
+
+    println!("\nThis replaces the character list and the tag list{} in {}.", if new_flags.is_empty() { "" } else { ", and adds the flags to pack.txt," }, pack::get().dir.display());
+    println!("Type 'YES' in uppercase to apply...");
+    let _ = io::stdout().flush();
+    let mut choice = String::default();
+    let _ = io::stdin().read_line(&mut choice);
+    if choice != "YES\n" {
+        restore();
+        println!("Aborted.");
+        return;
+    }
+    let source = format!("the dataset: {}", dataset.version);
+    if let Err(e) = groups::write(&tags, &source, &pack::get().tags_path()) {
+        restore();
+        println!("Couldn't write the tag list: {}", e);
+        return;
+    }
+    if !new_flags.is_empty() {
+        if let Err(e) = pack::get().write() {
+            println!("Couldn't write pack.txt: {}", e);
+            return;
+        }
+    }
+    let mut stock = format!("# This is the {} list used by Tohorank to generate its data.\n# Generated from {}\n\n", pack::get().noun, source);
+    for (entry, listed) in dataset.charas.iter().zip(listed.iter()) {
+        stock.push_str(&stock_line(entry, &listed.meta));
+        stock.push('\n');
+    }
+    if let Err(e) = fs::write(pack::get().list_path(), stock) {
+        println!("Couldn't write the stock list: {}", e);
+        return;
+    }
+    update.commit(touhous, data_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = r#"
+dataSetVersion = "2024-05-01"; // comments too
+dataSet[dataSetVersion] = {};
+
+dataSet[dataSetVersion].options = [
+  {
+    name: "Filter by Series Entry",
+    key: "series",
+    tooltip: "Check this to restrict to certain series.",
+    checked: false,
+    sub: [
+      { name: "Embodiment of Scarlet Devil", tooltip: "06 - the Embodiment of Scarlet Devil", key: "EoSD" },
+      { name: "Books and CDs", key: "book" },
+    ]
+  },
+  {
+    name: "Filter by Stage",
+    key: "stage",
+    sub: [{ name: "Stage 2", key: "st2" }]
+  },
+  {
+    name: "Remove PC-98 Duplicates",
+    key: "pc98",
+    tooltip: 'Check this to remove PC-98 duplicates.',
+    checked: false,
+  },
+];
+
+dataSet[dataSetVersion].characterData = [
+  {
+    name: "Cirno",
+    img: "abc.png",
+    opts: {
+      series: ["EoSD", "book"],
+      stage: ["st2"],
+    }
+  },
+  { name: "Hakurei Reimu (PC-98)", opts: { series: ["book"], pc98: true, notgirl: false } },
+];
+"#;
+
+    fn tag(id: u32, key: &str, name: &str, aliases: &[&str])
+    -> TagInfo {
+        TagInfo {
+            id,
+            key: key.to_string(),
+            category: "work".to_string(),
+            name: name.to_string(),
+            exname: String::new(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn reads_a_dataset() {
+        let dataset = parse(DATA).unwrap();
+        assert_eq!(dataset.version, "2024-05-01");
+        let keys: Vec<(&str, &str)> = dataset.tags.iter().map(|a| (a.key.as_str(), a.category.as_str())).collect();
+        assert_eq!(keys, vec![("EoSD", "work"), ("book", "work"), ("st2", "stage")]);
+        assert_eq!(dataset.tags[0].aliases, vec!["th06"]);
+        assert!(dataset.tags[1].aliases.is_empty());
+        assert_eq!(dataset.flags.len(), 1);
+        assert_eq!((dataset.flags[0].key.as_str(), dataset.flags[0].name.as_str()), ("pc98", "Remove PC-98 Duplicates"));
+        assert_eq!(dataset.charas.len(), 2);
+        assert_eq!(dataset.charas[0].name, "Cirno");
+        assert_eq!(dataset.charas[0].tags, vec!["EoSD", "book", "st2"]);
+        assert!(dataset.charas[0].flags.is_empty());
+        assert_eq!(dataset.charas[1].flags, vec!["pc98"]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(parse("var x = 1;").is_err());
+    }
+
+    #[test]
+    fn merging_tags_keeps_ids() {
+        let current = vec![tag(0, "EoSD", "Old name", &["th06"]), tag(7, "PCB", "Perfect Cherry Blossom", &[])];
+        let new = vec![tag(0, "eosd", "Embodiment of Scarlet Devil", &["th06", "koumakyou"]), tag(0, "IN", "Imperishable Night", &[])];
+        let (merged, added) = merge_tags(&current, &new);
+        assert_eq!(added, vec!["IN"]);
+        assert_eq!(merged.len(), 3);
+        assert_eq!((merged[0].id, merged[0].key.as_str(), merged[0].name.as_str()), (0, "EoSD", "Embodiment of Scarlet Devil"));
+        assert_eq!(merged[0].aliases, vec!["th06", "koumakyou"]);
+        assert_eq!(merged[1].id, 7);
+        assert_eq!((merged[2].id, merged[2].key.as_str()), (8, "IN"));
+    }
+}
@@ -0,0 +1,239 @@
+// Tohorank: comparing two rankings of ours
+// Either side can be the live ranking, the ranking some sessions ago,
+// another profile's data file, or the match log rated another way.
+
+use crate::{Chara, Glicko, Session, chart, data, glicko, legacy, norm};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use colored::Colorize;
+
+// how many to show in the lists and the bump chart
+const SHOW_LEN: usize = 15;
+
+// Where a ranking comes from
+pub enum Source {
+    Now,                // the live ranking
+    Ago(usize),         // n sessions ago, from the timelines
+    Profile(PathBuf),   // another data file
+    Replay,             // Glicko-2 over the match log from scratch, one period per session
+    BradleyTerry,       // Bradley-Terry fit of the match log
+}
+
+impl Source {
+    // "now", "5", "-5", "replay", "bt", or a path to a data file (or its folder)
+    pub fn parse(arg: &str)
+    -> Source {
+        let arg = arg.trim();
+        if arg.is_empty() || arg == "now" {
+            Source::Now
+        } else if let Ok(n) = arg.trim_start_matches('-').parse::<usize>() {
+            if n == 0 { Source::Now } else { Source::Ago(n) }
+        } else if arg == "replay" {
+            Source::Replay
+        } else if arg == "bt" || arg.starts_with("bradley") {
+            Source::BradleyTerry
+        } else {
+            let path = PathBuf::from(arg);
+            if path.is_dir() {
+                Source::Profile(path.join("data.bin"))
+            } else {
+                Source::Profile(path)
+            }
+        }
+    }
+
+    pub fn label(&self)
+    -> String {
+        match self {
+            Source::Now => "now".to_string(),
+            Source::Ago(1) => "1 session ago".to_string(),
+            Source::Ago(n) => format!("{} sessions ago", n),
+            Source::Profile(path) => path.display().to_string(),
+            Source::Replay => "Glicko-2 replay".to_string(),
+            Source::BradleyTerry => "Bradley-Terry".to_string(),
+        }
+    }
+}
+
+// Names and ratings of a ranking, best first
+pub fn ordering(source: &Source, touhous: &[Chara], log: &[Session])
+-> Result<Vec<(String, f64)>, String> {
+    let mut result: Vec<(String, f64)> = match source {
+        Source::Now => touhous.iter()
+            .filter(|th| !th.dont_know())
+            .map(|th| (th.name.clone(), th.rank.rate))
+            .collect(),
+        Source::Ago(n) => {
+            let result: Vec<(String, f64)> = touhous.iter()
+                .filter(|th| !th.dont_know() && th.hist.timeline.len() > *n)
+                .map(|th| (th.name.clone(), th.hist.timeline[th.hist.timeline.len() - 1 - n].rate))
+                .collect();
+            if result.is_empty() {
+                return Err(format!("there aren't {} sessions in the history yet", n));
+            }
+            result
+        },
+        Source::Profile(path) => {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            let other: Vec<Chara> = match data::decode(file) {
+                Ok(other) => other,
+                Err(_) => legacy::read(path).ok_or("not a tohorank data file")?,
+            };
+            other.into_iter()
+                .filter(|th| !th.dont_know())
+                .map(|th| (th.name, th.rank.rate))
+                .collect()
+        },
+        Source::Replay => {
+            let ratings = replay(touhous, log);
+            ratings.into_iter()
+                .filter(|(id, _)| !touhous[*id].dont_know())
+                .map(|(id, rank)| (touhous[id].name.clone(), rank.rate))
+                .collect()
+        },
+        Source::BradleyTerry => {
+            let ratings = bradley_terry(log);
+            ratings.into_iter()
+                .filter(|(id, _)| *id < touhous.len() && !touhous[*id].dont_know())
+                .map(|(id, rate)| (touhous[id].name.clone(), rate))
+                .collect()
+        },
+    };
+    if result.is_empty() {
+        return Err("there's no one in it".to_string());
+    }
+    result.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    Ok(result)
+}
+
+// Glicko-2 over the whole match log, everyone starting fresh
+pub fn replay(touhous: &[Chara], log: &[Session])
+-> HashMap<usize, Glicko> {
+    let mut ratings: HashMap<usize, Glicko> = (0..touhous.len())
+        .map(|id| (id, Glicko { rate: 1500.0, devi: 350.0, vola: 0.06 }))
+        .collect();
+    for session in log.iter() {
+        glicko::rate(&mut ratings, &session.records);
+    }
+    // only the ones who've been battled say anything
+    ratings.retain(|id, _| log.iter().flat_map(|a| a.records.iter()).any(|a| a.one == *id || a.two == *id));
+    ratings
+}
+
+// Bradley-Terry strengths from the match log, by minorization-maximization
+// everyone gets a virtual win and loss against an average opponent,
+// or the undefeated would go to infinity; returned on the Elo scale
+pub fn bradley_terry(log: &[Session])
+-> HashMap<usize, f64> {
+    let mut wins: HashMap<usize, f64> = HashMap::new();
+    let mut games: HashMap<(usize, usize), f64> = HashMap::new();
+    for battle in log.iter().flat_map(|a| a.records.iter()) {
+        // both disliked says nothing about who's better
+        if battle.res == 2.0 {
+            continue;
+        }
+        *wins.entry(battle.one).or_default() += battle.res as f64;
+        *wins.entry(battle.two).or_default() += 1.0 - battle.res as f64;
+        *games.entry((battle.one.min(battle.two), battle.one.max(battle.two))).or_default() += 1.0;
+    }
+    let mut strength: HashMap<usize, f64> = wins.keys().map(|id| (*id, 1.0)).collect();
+    for _ in 0..500 {
+        let mut sums: HashMap<usize, f64> = strength.iter()
+            .map(|(id, p)| (*id, 2.0 / (p + 1.0)))
+            .collect();
+        for ((a, b), n) in games.iter() {
+            let share = n / (strength[a] + strength[b]);
+            *sums.get_mut(a).unwrap() += share;
+            *sums.get_mut(b).unwrap() += share;
+        }
+        let mut change: f64 = 0.0;
+        for (id, p) in strength.iter_mut() {
+            let new = (wins[id] + 1.0) / sums[id];
+            change = change.max((new - *p).abs() / *p);
+            *p = new;
+        }
+        if change < 1e-9 {
+            break;
+        }
+    }
+    // relative to the geometric mean, like Elo ratings around 1500
+    let mean = strength.values().map(|p| p.ln()).sum::<f64>() / strength.len().max(1) as f64;
+    strength.into_iter()
+        .map(|(id, p)| (id, 1500.0 + 400.0 * (p.ln() - mean) / 10f64.ln()))
+        .collect()
+}
+
+// The `diff` command: `diff [source] (vs [source])`, the second one is "now" if left out
+pub fn show(line: &str, touhous: &[Chara], data_path: &Path) {
+    let args = line.trim().split_once(' ').map(|a| a.1.trim()).unwrap_or("");
+    if args.is_empty() {
+        println!("Usage: diff [source] (vs [source])");
+        println!("ⓘ A source is 'now', a number of sessions ago, 'replay' (Glicko-2 over the match log),");
+        println!("  'bt' (Bradley-Terry fit of the match log) or the path to another data file.\n");
+        return;
+    }
+    let (one, two) = match args.split_once(" vs ") {
+        Some((one, two)) => (Source::parse(one), Source::parse(two)),
+        None => (Source::parse(args), Source::Now),
+    };
+    let log = data::read_log(data_path);
+    let mut orderings = Vec::with_capacity(2);
+    for source in [&one, &two] {
+        match ordering(source, touhous, &log) {
+            Ok(list) => { orderings.push(list); },
+            Err(e) => {
+                println!("Couldn't read \"{}\": {}", source.label(), e);
+                return;
+            },
+        }
+    }
+    let names: Vec<Vec<String>> = orderings.iter()
+        .map(|list| list.iter().map(|a| a.0.clone()).collect())
+        .collect();
+
+    println!("==> {} {} {}", one.label().bold(), "vs".truecolor(182, 185, 191), two.label().bold());
+    let list = norm::pair_up(&names[0], &names[1]);
+    norm::show(&list, &names[0], &names[1], &mut rand::thread_rng());
+
+    // Biggest disagreements, among the characters in both
+    println!("==> {}", "BIGGEST DISAGREEMENTS".bold());
+    let mut moves: Vec<(&String, &(usize, usize))> = list.iter().collect();
+    moves.sort_by_key(|a| (std::cmp::Reverse(a.1.0.abs_diff(a.1.1)), a.1.1));
+    println!("    {:<34}{:>6}{:>6}", "", "#1", "#2");
+    for (name, (a, b)) in moves.iter().take(SHOW_LEN) {
+        if a == b {
+            break;
+        }
+        println!("    {:<34}{:>6}{:>6}   {}",
+            name,
+            a,
+            b,
+            if b < a {
+                format!("🡽 {}", a - b).blue()
+            } else {
+                format!("🡾 {}", b - a).red()
+            }
+        );
+    }
+    println!();
+
+    // Bump chart, every session in between if both are from the timelines
+    let steps: Vec<usize> = match (&one, &two) {
+        (Source::Ago(a), Source::Ago(b)) if a > b => (*b..=*a).rev().collect(),
+        (Source::Ago(a), Source::Ago(b)) => (*a..=*b).collect(),
+        (Source::Ago(a), Source::Now) => (0..=*a).rev().collect(),
+        (Source::Now, Source::Ago(a)) => (0..=*a).collect(),
+        _ => Vec::new(),
+    };
+    let columns: Vec<Vec<String>> = if steps.len() > 2 {
+        steps.iter()
+            .filter_map(|n| ordering(&if *n == 0 { Source::Now } else { Source::Ago(*n) }, touhous, &log).ok())
+            .map(|list| list.into_iter().map(|a| a.0).collect())
+            .collect()
+    } else {
+        names
+    };
+    println!("==> {}", "MOVEMENTS".bold());
+    chart::bump(&columns, SHOW_LEN);
+}
@@ -0,0 +1,268 @@
+// Tohorank: rankings and tier lists as images
+// Everything is drawn as SVG first, PNGs are rendered from that with resvg.
+
+use crate::{Chara, meta, pack, tiers};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::groups::Tags;
+
+// colors, same as the terminal
+const GOLD: &str = "#f5d45f";
+const SILVER: &str = "#b4f5d4";
+const BRONZE: &str = "#f08c5f";
+const TIER_COLORS: [&str; 5] = ["#f5d45f", "#f08c5f", "#b4f5d4", "#8cb4fa", "#b6b9bf"];
+const BACKGROUND: &str = "#1e1f24";
+const TEXT: &str = "#e8e8e8";
+const GREY: &str = "#9a9ca3";
+
+// Escape text for SVG/HTML
+pub fn escape(text: &str)
+-> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Plain base64, for embedding images
+pub fn base64(data: &[u8])
+-> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// The portrait of a character as a data URI: the one the list points at,
+// or one in the portraits folder named after the character, like "Cirno.png"
+pub fn portrait(chara: &Chara, portraits: Option<&Path>)
+-> Option<String> {
+    let types = [("png", "image/png"), ("jpg", "image/jpeg"), ("jpeg", "image/jpeg"), ("gif", "image/gif"), ("webp", "image/webp")];
+    if let Some(path) = meta::portrait(chara) {
+        let ext = path.extension().map(|a| a.to_string_lossy().to_lowercase()).unwrap_or_default();
+        if let (Some((_, mime)), Ok(data)) = (types.iter().find(|a| a.0 == ext), fs::read(&path)) {
+            return Some(format!("data:{};base64,{}", mime, base64(&data)));
+        }
+    }
+    let portraits = portraits?;
+    for (ext, mime) in types {
+        if let Ok(data) = fs::read(portraits.join(format!("{}.{}", chara.name, ext))) {
+            return Some(format!("data:{};base64,{}", mime, base64(&data)));
+        }
+    }
+    None
+}
+
+// The file of a profile that names its portraits folder
+fn portraits_link(data_path: &Path)
+-> PathBuf {
+    data_path.with_file_name("portraits.link")
+}
+
+// The portraits folder, if one is set
+pub fn portraits_path(data_path: &Path)
+-> Option<PathBuf> {
+    fs::read_to_string(portraits_link(data_path)).ok()
+        .map(|a| PathBuf::from(a.trim()))
+        .filter(|a| !a.as_os_str().is_empty())
+}
+
+// The `portraits` command: show, set or unset the folder
+pub fn portraits_command(line: &str, data_path: &Path) {
+    let link = portraits_link(data_path);
+    match line.trim().split_once(' ').map(|a| a.1.trim()) {
+        None => match portraits_path(data_path) {
+            Some(dir) => { println!("Portraits are read from {}.", dir.display()); },
+            None => { println!("No portraits folder set, `portraits [folder]` sets one."); },
+        },
+        Some("off") => {
+            let _ = fs::remove_file(&link);
+            println!("Exports won't have portraits from a folder anymore.");
+        },
+        Some(dir) => {
+            let dir = match PathBuf::from(dir).canonicalize() {
+                Ok(dir) if dir.is_dir() => dir,
+                _ => {
+                    println!("\"{}\" is not a folder.", dir);
+                    return;
+                },
+            };
+            match fs::write(&link, dir.display().to_string()) {
+                Ok(_) => { println!("Portraits are read from {}, named after the {}, like \"Cirno.png\".", dir.display(), pack::get().nouns()); },
+                Err(e) => { println!("Couldn't save the folder: {}", e); },
+            }
+        },
+    }
+}
+
+// Short keys of the works a character appears in, in release order
+fn work_tags(chara: &Chara)
+-> String {
+    let works: Vec<String> = Tags::iter()
+        .filter(|tag| tag.is_series_tag() && chara.has_tag(tag))
+        .map(|tag| tag.key())
+        .collect();
+    if works.len() > 8 {
+        format!("{} +{}", works[..8].join(" "), works.len() - 8)
+    } else {
+        works.join(" ")
+    }
+}
+
+// The top n of the ranking as an SVG
+pub fn list_svg(mut touhous: Vec<&Chara>, first: usize, portraits: Option<&Path>)
+-> String {
+    touhous.retain(|th| !th.dont_know());
+    touhous.sort_by(|a, b| b.rank.rate.partial_cmp(&a.rank.rate).unwrap());
+    touhous.truncate(first);
+
+    const ROW: usize = 48;
+    const TOP: usize = 70;
+    const WIDTH: usize = 960;
+    let height = TOP + ROW * touhous.len() + 20;
+    // the rating scale of the bars
+    let low = touhous.iter().map(|th| th.rank.rate - th.rank.devi * 1.96).fold(f64::INFINITY, f64::min);
+    let high = touhous.iter().map(|th| th.rank.rate + th.rank.devi * 1.96).fold(f64::NEG_INFINITY, f64::max);
+    let (bar_x, bar_w) = (470.0, 360.0);
+    let scale = |rate: f64| bar_x + (rate - low) / (high - low).max(1.0) * bar_w;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"DejaVu Sans, Noto Sans, Arial, sans-serif\">\n\
+         <rect width=\"100%\" height=\"100%\" fill=\"{bg}\"/>\n\
+         <text x=\"20\" y=\"42\" font-size=\"26\" font-weight=\"bold\" fill=\"{fg}\">{t}: Top {n}</text>\n",
+        w = WIDTH, h = height, bg = BACKGROUND, fg = TEXT, t = escape(&pack::get().title), n = touhous.len()
+    );
+    let mut rank = 0;
+    let mut last_rating = f64::INFINITY;
+    for (n, th) in touhous.iter().enumerate() {
+        if th.rank.rate < last_rating {
+            rank = n + 1;
+            last_rating = th.rank.rate;
+        }
+        let y = TOP + ROW * n;
+        let color = match rank {
+            1 => GOLD,
+            2 => SILVER,
+            3 => BRONZE,
+            _ => TEXT,
+        };
+        if n % 2 == 1 {
+            svg.push_str(&format!("<rect x=\"0\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#ffffff\" fill-opacity=\"0.03\"/>\n", y, WIDTH, ROW));
+        }
+        svg.push_str(&format!("<text x=\"20\" y=\"{}\" font-size=\"18\" font-weight=\"bold\" fill=\"{}\">{}.</text>\n", y + 30, color, rank));
+        // portrait, if any
+        let name_x = match portrait(th, portraits) {
+            Some(uri) => {
+                svg.push_str(&format!("<image x=\"70\" y=\"{}\" width=\"40\" height=\"40\" href=\"{}\" preserveAspectRatio=\"xMidYMid slice\"/>\n", y + 4, uri));
+                120
+            },
+            None => 70,
+        };
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"17\" fill=\"{}\">{}</text>\n", name_x, y + 22, color, escape(&th.name)));
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"11\" fill=\"{}\">{}</text>\n", name_x, y + 39, GREY, escape(&work_tags(th))));
+        // rating bar and the uncertainty whisker
+        let (lo, mid, hi) = (
+            scale(th.rank.rate - th.rank.devi * 1.96),
+            scale(th.rank.rate),
+            scale(th.rank.rate + th.rank.devi * 1.96),
+        );
+        svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{:.1}\" height=\"14\" rx=\"3\" fill=\"{}\" fill-opacity=\"0.8\"/>\n", bar_x, y + 16, mid - bar_x, color));
+        svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"2\"/>\n", lo, y + 23, hi, y + 23, GREY));
+        svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"2\"/>\n", lo, y + 17, lo, y + 29, GREY));
+        svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"2\"/>\n", hi, y + 17, hi, y + 29, GREY));
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"14\" fill=\"{}\">{:.0} ± {:.0}</text>\n", bar_x + bar_w + 20.0, y + 28, TEXT, th.rank.rate, th.rank.devi * 1.96));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// A tier list as an SVG
+pub fn tiers_svg(mut touhous: Vec<&Chara>, method: &tiers::Method, portraits: Option<&Path>)
+-> String {
+    touhous.retain(|th| !th.dont_know());
+    touhous.sort_by(|a, b| b.rank.rate.partial_cmp(&a.rank.rate).unwrap());
+    let assigned = tiers::assign(&touhous, method);
+
+    const TILE_W: usize = 100;
+    const TILE_H: usize = 116;
+    const PER_ROW: usize = 9;
+    const LABEL: usize = 90;
+    const WIDTH: usize = LABEL + TILE_W * PER_ROW + 10;
+
+    let mut body = String::new();
+    let mut y = 70;
+    for (tier, label) in tiers::TIERS.iter().enumerate() {
+        let members: Vec<&&Chara> = touhous.iter()
+            .zip(assigned.iter())
+            .filter(|(_, t)| **t == tier)
+            .map(|(th, _)| th)
+            .collect();
+        let rows = members.len().div_ceil(PER_ROW).max(1);
+        let height = rows * TILE_H + 8;
+        body.push_str(&format!("<rect x=\"0\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n", y, LABEL, height, TIER_COLORS[tier]));
+        body.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"40\" font-weight=\"bold\" text-anchor=\"middle\" fill=\"{}\">{}</text>\n",
+            LABEL / 2, y + height / 2 + 14, BACKGROUND, label));
+        for (n, th) in members.iter().enumerate() {
+            let x = LABEL + 5 + (n % PER_ROW) * TILE_W;
+            let ty = y + 4 + (n / PER_ROW) * TILE_H;
+            match portrait(th, portraits) {
+                Some(uri) => {
+                    body.push_str(&format!("<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" href=\"{}\" preserveAspectRatio=\"xMidYMid slice\"/>\n",
+                        x + 4, ty + 2, TILE_W - 8, TILE_W - 8, uri));
+                },
+                None => {
+                    body.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"6\" fill=\"{}\" fill-opacity=\"0.25\"/>\n",
+                        x + 4, ty + 2, TILE_W - 8, TILE_W - 8, TIER_COLORS[tier]));
+                    body.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"13\" text-anchor=\"middle\" fill=\"{}\">{:.0}</text>\n",
+                        x + TILE_W / 2, ty + TILE_W / 2 + 4, TEXT, th.rank.rate));
+                },
+            }
+            let short: String = if th.name.chars().count() > 15 {
+                th.name.chars().take(14).chain(std::iter::once('…')).collect()
+            } else {
+                th.name.clone()
+            };
+            body.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"11\" text-anchor=\"middle\" fill=\"{}\">{}</text>\n",
+                x + TILE_W / 2, ty + TILE_W + 8, TEXT, escape(&short)));
+        }
+        y += height + 4;
+    }
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"DejaVu Sans, Noto Sans, Arial, sans-serif\">\n\
+         <rect width=\"100%\" height=\"100%\" fill=\"{bg}\"/>\n\
+         <text x=\"20\" y=\"42\" font-size=\"26\" font-weight=\"bold\" fill=\"{fg}\">{t}: Tier list ({m})</text>\n",
+        w = WIDTH, h = y + 10, bg = BACKGROUND, fg = TEXT, t = escape(&pack::get().title), m = method.name()
+    );
+    svg.push_str(&body);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Writes the SVG to path, as a PNG if the extension says so
+pub fn save(svg: &str, path: &Path)
+-> Result<(), String> {
+    match path.extension().and_then(|a| a.to_str()).map(|a| a.to_lowercase()).as_deref() {
+        Some("svg") => fs::write(path, svg).map_err(|e| e.to_string()),
+        Some("png") => {
+            let mut options = resvg::usvg::Options::default();
+            options.fontdb_mut().load_system_fonts();
+            let tree = resvg::usvg::Tree::from_str(svg, &options).map_err(|e| e.to_string())?;
+            let size = tree.size().to_int_size();
+            let mut pixmap = resvg::tiny_skia::Pixmap::new(size.width(), size.height())
+                .ok_or("image is too large")?;
+            resvg::render(&tree, resvg::tiny_skia::Transform::default(), &mut pixmap.as_mut());
+            pixmap.save_png(path).map_err(|e| e.to_string())
+        },
+        _ => Err("the file name should end in .svg or .png".to_string()),
+    }
+}
@@ -1,6 +1,6 @@
 // TOhorank: Glicko-2 rating system
 
-use crate::{chara, Chara, Match};
+use crate::{chara, Chara, Glicko, Match};
 use std::f64::consts::PI;
 use std::collections::HashMap;
 use colored::Colorize;
@@ -13,6 +13,55 @@ pub fn calc(touhous: &mut Vec<Chara>, records: &Vec<Match>) {
         chara::update_history(touhous, records);
     }
 
+    // save old ratings of battled characters
+    let mut old_ratings: HashMap<usize, f64> = HashMap::new();
+    for battle in records.iter() {
+        old_ratings.insert(battle.one, touhous[battle.one].rank.rate);
+        old_ratings.insert(battle.two, touhous[battle.two].rank.rate);
+    }
+
+    // everyone takes part in the rating period
+    let mut ratings: HashMap<usize, Glicko> = touhous.iter()
+        .enumerate()
+        .map(|(id, th)| (id, th.rank.clone()))
+        .collect();
+    rate(&mut ratings, records);
+    for (id, rank) in ratings {
+        touhous[id].rank = rank;
+    }
+    if !records.is_empty() {
+        chara::update_timeline(touhous);
+    }
+
+    // display changes
+    println!("----- Changes -----");
+    let mut diffs: Vec<(usize, f64)> = old_ratings
+        .iter()
+        .map(|(id, old_rt)| (*id, touhous[*id].rank.rate - old_rt))
+        .collect();
+    diffs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+    for (th, diff) in diffs {
+        println!("{:<26}| {:<5} | {}",
+            touhous[th].name,
+            format!("{:.0}", touhous[th].rank.rate),
+            if diff > 0.0 {
+                format!("{:.0}", diff).blue()
+            } else {
+                format!("{:.0}", diff).red()
+            }
+        );
+    }
+    println!("-------------------");
+}
+
+// Runs one rating period over the given ratings (keyed by global ID)
+// Battles involving someone not in ratings are ignored
+pub fn rate(ratings: &mut HashMap<usize, Glicko>, records: &[Match]) {
+    let records: Vec<&Match> = records.iter()
+        .filter(|a| ratings.contains_key(&a.one) && ratings.contains_key(&a.two))
+        .collect();
+
     // First we need to calculate the quantities v and delta
     let mut qt_v: HashMap<usize, f64> = HashMap::new();
     let mut qt_d: HashMap<usize, f64> = HashMap::new();
@@ -25,23 +74,17 @@ pub fn calc(touhous: &mut Vec<Chara>, records: &Vec<Match>) {
         qt_d.insert(battle.two, 0.0);
     }
 
-    // save old ratings of battled characters
-    let mut old_ratings: HashMap<usize, f64> = HashMap::new();
-    for th in qt_v.keys() {
-        old_ratings.insert(*th, touhous[*th].rank.rate);
-    }
-
     // transform to the glicko-2 scale
-    for th in touhous.iter_mut() {
-        glicko_two_scale(&mut th.rank.rate, &mut th.rank.devi);
+    for rank in ratings.values_mut() {
+        glicko_two_scale(&mut rank.rate, &mut rank.devi);
     }
 
     for battle in records.iter() {
         // fetch numbers
-        let r1 = touhous[battle.one].rank.rate;
-        let r2 = touhous[battle.two].rank.rate;
-        let rd1 = touhous[battle.one].rank.devi;
-        let rd2 = touhous[battle.two].rank.devi;
+        let r1 = ratings[&battle.one].rate;
+        let r2 = ratings[&battle.two].rate;
+        let rd1 = ratings[&battle.one].devi;
+        let rd2 = ratings[&battle.two].devi;
         let (s1, s2) = if battle.res == 2.0 {
             (0.25, 0.25) // both sides lose, but not as much as when only one side loses
         } else {
@@ -87,11 +130,12 @@ pub fn calc(touhous: &mut Vec<Chara>, records: &Vec<Match>) {
 
     // update the volatility for all characters in this session
     for th in qt_v.keys() {
-        touhous[*th].rank.vola = calc_new_volatility(
+        let rank = ratings.get_mut(th).unwrap();
+        rank.vola = calc_new_volatility(
             &qt_v[th],
             &qt_d[th],
-            &touhous[*th].rank.vola,
-            &touhous[*th].rank.devi,
+            &rank.vola,
+            &rank.devi,
             &TAU,
             &CONV_TOLERANCE
         );
@@ -99,55 +143,48 @@ pub fn calc(touhous: &mut Vec<Chara>, records: &Vec<Match>) {
 
     // now we update the rating deviations,
     // first round on all characters
-    for th in touhous.iter_mut() {
-        th.rank.devi = adjust_deviation(
-            &th.rank.devi,
-            &th.rank.vola
+    for rank in ratings.values_mut() {
+        rank.devi = adjust_deviation(
+            &rank.devi,
+            &rank.vola
         );
     }
     // second round on battled characters
     for th in qt_v.keys() {
-        touhous[*th].rank.devi = calc_new_deviation(
-            &touhous[*th].rank.devi,
+        let rank = ratings.get_mut(th).unwrap();
+        rank.devi = calc_new_deviation(
+            &rank.devi,
             &qt_v[th]
         );
     }
 
     // finally, we calculate the new ratings
     for th in qt_v.keys() {
-        touhous[*th].rank.rate = calc_new_rating(
-            &touhous[*th].rank.rate,
-            &touhous[*th].rank.devi,
+        let rank = ratings.get_mut(th).unwrap();
+        rank.rate = calc_new_rating(
+            &rank.rate,
+            &rank.devi,
             &qt_v[th],
             &qt_d[th]
         );
     }
 
     // transform back to glicko scale
-    for th in touhous.iter_mut() {
-        glicko_one_scale(&mut th.rank.rate, &mut th.rank.devi);
+    for rank in ratings.values_mut() {
+        glicko_one_scale(&mut rank.rate, &mut rank.devi);
     }
+}
 
-    // display changes
-    println!("----- Changes -----");
-    let mut diffs: Vec<(usize, f64)> = old_ratings
-        .iter()
-        .map(|(id, old_rt)| (*id, touhous[*id].rank.rate - old_rt))
+// Provisional ratings of the session pool if the session ended right now
+// Nothing is written back, the returned map is keyed by global ID
+pub fn provisional(pool: &[&mut Chara], indices: &[usize], records: &[Match])
+-> HashMap<usize, Glicko> {
+    let mut ratings: HashMap<usize, Glicko> = indices.iter()
+        .zip(pool.iter())
+        .map(|(id, th)| (*id, th.rank.clone()))
         .collect();
-    diffs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-
-    for (th, diff) in diffs {
-        println!("{:<26}| {:<5} | {}",
-            touhous[th].name,
-            format!("{:.0}", touhous[th].rank.rate),
-            if diff > 0.0 {
-                format!("{:.0}", diff).blue()
-            } else {
-                format!("{:.0}", diff).red()
-            }
-        );
-    }
-    println!("-------------------");
+    rate(&mut ratings, records);
+    ratings
 }
 
 // convert from glicko to glicko-2
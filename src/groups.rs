@@ -1,216 +1,238 @@
 // Character groups for detailed stats
-// Generated from tohosort's dataset: 2023-09-24
+// The tags are data: read from tags.txt of the pack at startup,
+// or the stock list built into the binary. Characters keep the tag ids,
+// so a new release only needs new lines in tags.txt.
 
+use crate::pack::Pack;
 use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
-use strum_macros::EnumIter;
-
-// Tell the compiler to stop complaining
-#[allow(non_camel_case_types)]
-
-// Group by the work they appeared in
-// taken from tohosort
-
-#[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Debug, Clone, EnumIter)]
-pub enum Tags {
-	book,
-	HRtP,
-	SoEW,
-	PoDD,
-	LLS,
-	MS,
-	EoSD,
-	PCB,
-	IaMP,
-	IN,
-	PoFV,
-	StB,
-	MoF,
-	SWR,
-	SA,
-	UFO,
-	soku,
-	DS,
-	GFW,
-	TD,
-	HM,
-	DDC,
-	ISC,
-	ULiL,
-	LoLK,
-	AoCF,
-	HSiFS,
-	VD,
-	WBaWC,
-	SFW,
-	UM,
-	CBM,
-	UDoALG,
-	// Stages
-	st1,
-	st2,
-	st3,
-	st4,
-	st5,
-	st6,
-	ex,
+use std::sync::RwLock;
+
+// the stock tags, same format as ~/.tohorank/tags.txt
+pub const STOCK: &str = include_str!("tags.txt");
+
+// A tag id, as stored in the data file
+#[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub struct Tags(pub u32);
+
+// Everything about a tag
+#[derive(Debug, Clone)]
+pub struct TagInfo {
+	pub id: u32,
+	pub key: String,		// what filters use, like "EoSD"
+	pub category: String,	// "work", "stage", or anything else
+	pub name: String,
+	pub exname: String,		// the game's number and title, for works
+	pub aliases: Vec<String>,	// other keys, like "th06"
+}
+
+// the tags in use, in the order of the file, then the custom ones
+static TAGSET: RwLock<Vec<TagInfo>> = RwLock::new(Vec::new());
+// who has the custom tags, by id
+static MEMBERS: RwLock<Vec<(u32, Vec<String>)>> = RwLock::new(Vec::new());
+
+// custom tags get ids from here, far from any dataset
+pub const CUSTOM_BASE: u32 = 1 << 24;
+
+// Reads a tag list, lines are `id; key; category; name; extra name; aliases`
+pub fn parse(text: &str)
+-> Result<Vec<TagInfo>, String> {
+	let mut tags: Vec<TagInfo> = Vec::new();
+	for (number, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let fields: Vec<&str> = line.split(';').map(|a| a.trim()).collect();
+		if fields.len() < 4 {
+			return Err(format!("line {}: expected `id; key; category; name`", number + 1));
+		}
+		let id: u32 = fields[0].parse()
+			.map_err(|_| format!("line {}: \"{}\" is not an id", number + 1, fields[0]))?;
+		if fields[1].is_empty() || fields[1].contains(' ') || fields[1].starts_with('-') {
+			return Err(format!("line {}: \"{}\" can't be a key", number + 1, fields[1]));
+		}
+		if let Some(other) = tags.iter().find(|a| a.id == id || a.key.eq_ignore_ascii_case(fields[1])) {
+			return Err(format!("line {}: {} is already used by {}", number + 1, if other.id == id { "the id" } else { "the key" }, other.key));
+		}
+		tags.push(TagInfo {
+			id,
+			key: fields[1].to_string(),
+			category: fields[2].to_lowercase(),
+			name: fields[3].to_string(),
+			exname: fields.get(4).unwrap_or(&"").to_string(),
+			aliases: fields.get(5).unwrap_or(&"").split(' ').filter(|a| !a.is_empty()).map(|a| a.to_string()).collect(),
+		});
+	}
+	Ok(tags)
+}
+
+// Use these tags from now on, the custom ones stay
+pub fn set(mut tags: Vec<TagInfo>) {
+	let mut tagset = TAGSET.write().unwrap();
+	tags.extend(tagset.drain(..).filter(|a| a.id >= CUSTOM_BASE));
+	*tagset = tags;
+}
+
+// Use these custom tags and members from now on
+pub fn set_custom(custom: Vec<(TagInfo, Vec<String>)>) {
+	let mut tagset = TAGSET.write().unwrap();
+	tagset.retain(|a| a.id < CUSTOM_BASE);
+	let mut members = MEMBERS.write().unwrap();
+	members.clear();
+	for (tag, names) in custom {
+		members.push((tag.id, names));
+		tagset.push(tag);
+	}
+}
+
+// The custom tags and their members
+pub fn custom()
+-> Vec<(TagInfo, Vec<String>)> {
+	let members = MEMBERS.read().unwrap();
+	TAGSET.read().unwrap().iter()
+		.filter(|a| a.id >= CUSTOM_BASE)
+		.map(|a| (a.clone(), members.iter().find(|b| b.0 == a.id).map(|b| b.1.clone()).unwrap_or_default()))
+		.collect()
+}
+
+// True if the character has the custom tag
+pub fn is_member(tag: &Tags, name: &str)
+-> bool {
+	tag.0 >= CUSTOM_BASE && MEMBERS.read().unwrap().iter().any(|a| a.0 == tag.0 && a.1.iter().any(|b| b == name))
+}
+
+// Load the tags of the pack, the stock tags if it's the stock pack without one
+pub fn load(pack: &Pack) {
+	let tags_path = pack.tags_path();
+	let fallback = || if pack.stock { parse(STOCK).unwrap() } else { Vec::new() };
+	let tags = match fs::read_to_string(&tags_path) {
+		Ok(text) => parse(&text).unwrap_or_else(|e| {
+			println!("Tag list not good! ({}: {}) Using {}...", tags_path.display(), e, if pack.stock { "the stock tags" } else { "no tags" });
+			fallback()
+		}),
+		Err(_) => fallback(),
+	};
+	set(tags);
+}
+
+// The tags of the dataset
+pub fn all()
+-> Vec<TagInfo> {
+	TAGSET.read().unwrap().iter().filter(|a| a.id < CUSTOM_BASE).cloned().collect()
+}
+
+// Write a tag list in the format of tags.txt
+pub fn write(tags: &[TagInfo], source: &str, path: &Path)
+-> std::io::Result<()> {
+	let mut text = String::from("# This is the tag list used by Tohorank to group characters.\n");
+	text.push_str(&format!("# Generated from {}\n", source));
+	text.push_str("# id; key; category; name; extra name; aliases\n");
+	text.push_str("# The id is what the data file keeps, never reuse or renumber one.\n");
+	for tag in tags.iter() {
+		let line = format!("{}; {}; {}; {}; {}; {}", tag.id, tag.key, tag.category, tag.name, tag.exname, tag.aliases.join(" "));
+		text.push_str(line.trim_end());
+		text.push('\n');
+	}
+	fs::write(path, text)
+}
+
+// Every category, in order of appearance
+pub fn categories()
+-> Vec<String> {
+	let mut result: Vec<String> = Vec::new();
+	for tag in TAGSET.read().unwrap().iter() {
+		if !result.contains(&tag.category) {
+			result.push(tag.category.clone());
+		}
+	}
+	result
+}
+
+// Heading of a category, like "WORKS"
+pub fn category_title(category: &str)
+-> String {
+	match category {
+		"work" => "WORKS".to_string(),
+		"stage" => "STAGES".to_string(),
+		"custom" => "MY TAGS".to_string(),
+		other => other.to_uppercase(),
+	}
 }
 
 impl Tags {
+	// Every tag in use
+	pub fn iter() -> std::vec::IntoIter<Tags> {
+		TAGSET.read().unwrap().iter().map(|a| Tags(a.id)).collect::<Vec<Tags>>().into_iter()
+	}
+	fn info(&self) -> Option<TagInfo> {
+		TAGSET.read().unwrap().iter().find(|a| a.id == self.0).cloned()
+	}
+	// Short name used in filters
+	pub fn key(&self) -> String {
+		self.info().map(|a| a.key).unwrap_or(format!("#{}", self.0))
+	}
 	// Names of groups (touhou works and stages)
-	pub fn name(&self) -> &'static str {
-		match *self {
-			Tags::book	=> "Books and CDs",
-			Tags::HRtP	=> "Highly Responsive to Prayers",
-			Tags::SoEW	=> "The Story of Eastern Wonderland",
-			Tags::PoDD	=> "Phantasmagoria of Dim.Dream",
-			Tags::LLS	=> "Lotus Land Story",
-			Tags::MS	=> "Mystic Square",
-			Tags::EoSD	=> "The Embodiment of Scarlet Devil",
-			Tags::PCB	=> "Perfect Cherry Blossom",
-			Tags::IaMP	=> "Immaterial and Missing Power",
-			Tags::IN	=> "Imperishable Night",
-			Tags::PoFV	=> "Phantasmagoria of Flower View",
-			Tags::StB	=> "Shoot the Bullet",
-			Tags::MoF	=> "Mountain of Faith",
-			Tags::SWR	=> "Scarlet Weather Rhapsody",
-			Tags::SA	=> "Subterranean Animism",
-			Tags::UFO	=> "Undefined Fantastic Object",
-			Tags::soku	=> "Unthinkable Natural Law",
-			Tags::DS	=> "Double Spoiler",
-			Tags::GFW	=> "Great Fairy Wars",
-			Tags::TD	=> "Ten Desires",
-			Tags::HM	=> "Hopeless Masquerade",
-			Tags::DDC	=> "Double Dealing Character",
-			Tags::ISC	=> "Impossible Spell Card",
-			Tags::ULiL	=> "Urban Legend in Limbo",
-			Tags::LoLK	=> "Legacy of Lunatic Kingdom",
-			Tags::AoCF	=> "Antinomy of Common Flowers",
-			Tags::HSiFS	=> "Hidden Star in Four Seasons",
-			Tags::VD	=> "Violet Detector",
-			Tags::WBaWC	=> "Wily Beast and Weakest Creature",
-			Tags::SFW	=> "Sunken Fossil World",
-			Tags::UM	=> "Unconnected Marketeers",
-			Tags::CBM	=> "100th Black Market",
-			Tags::UDoALG	=> "Unfinished Dream of All Living Ghost",
-			// Stages
-			Tags::st1	=> "Stage 1",
-			Tags::st2	=> "Stage 2",
-			Tags::st3	=> "Stage 3",
-			Tags::st4	=> "Stage 4",
-			Tags::st5	=> "Stage 5/Penultimate",
-			Tags::st6	=> "Stage 6/Final",
-			Tags::ex	=> "Stage EX/Phantasm",
-		}
+	pub fn name(&self) -> String {
+		self.info().map(|a| a.name).unwrap_or(format!("Unknown group #{}", self.0))
 	}
 	// Touhou game titles.
-	pub fn exname(&self) -> &'static str {
-		match *self {
-			Tags::book	=> "",
-			Tags::HRtP	=> "01 - Reiiden",
-			Tags::SoEW	=> "02 - Fuumaroku",
-			Tags::PoDD	=> "03 - Yumejikuu",
-			Tags::LLS	=> "04 - Gensokyo",
-			Tags::MS	=> "05 - Kaikidan",
-			Tags::EoSD	=> "06 - Koumakyou",
-			Tags::PCB	=> "07 - Youyoumu",
-			Tags::IaMP	=> "07.5 - Suimusou",
-			Tags::IN	=> "08 - Eiyashou",
-			Tags::PoFV	=> "09 - Kaeidzuka",
-			Tags::StB	=> "09.5 - Bunkachou",
-			Tags::MoF	=> "10 - Fuujinroku",
-			Tags::SWR	=> "10.5 - Hisouten",
-			Tags::SA	=> "11 - Chireiden",
-			Tags::UFO	=> "12 - Seirensen",
-			Tags::soku	=> "12.3 - Hisoutensoku",
-			Tags::DS	=> "12.5 - Bunkachou",
-			Tags::GFW	=> "12.8 - Daisensou",
-			Tags::TD	=> "13 - Shinreibyou",
-			Tags::HM	=> "13.5 - Shinkirou",
-			Tags::DDC	=> "14 - Kishinjou",
-			Tags::ISC	=> "14.3 - Amanojaku",
-			Tags::ULiL	=> "14.5 - Shinpiroku",
-			Tags::LoLK	=> "15 - Kanjuden",
-			Tags::AoCF	=> "15.5 - Hyouibana",
-			Tags::HSiFS	=> "16 - Tenkuushou",
-			Tags::VD	=> "16.5 - Hifuu Nightmare Diary",
-			Tags::WBaWC	=> "17 - Kikeijuu",
-			Tags::SFW	=> "17.5 - Gouyoku Ibun",
-			Tags::UM	=> "18 - Kouryuudou",
-			Tags::CBM	=> "18.5 - Black Market of Bulletphilia",
-			Tags::UDoALG	=> "19 - Juuouen",
-			// Stages
-			Tags::st1	=> "",
-			Tags::st2	=> "",
-			Tags::st3	=> "",
-			Tags::st4	=> "",
-			Tags::st5	=> "",
-			Tags::st6	=> "",
-			Tags::ex	=> "",
-		}
+	pub fn exname(&self) -> String {
+		self.info().map(|a| a.exname).unwrap_or_default()
+	}
+	pub fn category(&self) -> String {
+		self.info().map(|a| a.category).unwrap_or_default()
+	}
+	// Returns true if it's made by the user
+	pub fn is_custom(&self) -> bool {
+		self.0 >= CUSTOM_BASE
 	}
 	// Returns true if it's a series tag
 	pub fn is_series_tag(&self) -> bool {
-		match self {
-			Tags::st1	=> false,
-			Tags::st2	=> false,
-			Tags::st3	=> false,
-			Tags::st4	=> false,
-			Tags::st5	=> false,
-			Tags::st6	=> false,
-			Tags::ex	=> false,
-			_	=> true,
-		}
+		self.category() == "work"
 	}
 }
 
-// String to name utility
+// String to name utility, keys and aliases in any case
 impl FromStr for Tags {
 	type Err = ();
 	fn from_str(input: &str) -> Result<Tags, Self::Err> {
-		match input.to_lowercase().as_str() {
-			"book"	=> Ok(Tags::book),
-			"hrtp"	| "th01"	=> Ok(Tags::HRtP),
-			"soew"	| "th02"	=> Ok(Tags::SoEW),
-			"podd"	| "th03"	=> Ok(Tags::PoDD),
-			"lls"	| "th04"	=> Ok(Tags::LLS),
-			"ms"	| "th05"	=> Ok(Tags::MS),
-			"eosd"	| "th06"	=> Ok(Tags::EoSD),
-			"pcb"	| "th07"	=> Ok(Tags::PCB),
-			"iamp"	| "th07.5"	=> Ok(Tags::IaMP),
-			"in"	| "th08"	=> Ok(Tags::IN),
-			"pofv"	| "th09"	=> Ok(Tags::PoFV),
-			"stb"	| "th09.5"	=> Ok(Tags::StB),
-			"mof"	| "th10"	=> Ok(Tags::MoF),
-			"swr"	| "th10.5"	=> Ok(Tags::SWR),
-			"sa"	| "th11"	=> Ok(Tags::SA),
-			"ufo"	| "th12"	=> Ok(Tags::UFO),
-			"soku"	| "th12.3"	=> Ok(Tags::soku),
-			"ds"	| "th12.5"	=> Ok(Tags::DS),
-			"gfw"	| "th12.8"	=> Ok(Tags::GFW),
-			"td"	| "th13"	=> Ok(Tags::TD),
-			"hm"	| "th13.5"	=> Ok(Tags::HM),
-			"ddc"	| "th14"	=> Ok(Tags::DDC),
-			"isc"	| "th14.3"	=> Ok(Tags::ISC),
-			"ulil"	| "th14.5"	=> Ok(Tags::ULiL),
-			"lolk"	| "th15"	=> Ok(Tags::LoLK),
-			"aocf"	| "th15.5"	=> Ok(Tags::AoCF),
-			"hsifs"	| "th16"	=> Ok(Tags::HSiFS),
-			"vd"	| "th16.5"	=> Ok(Tags::VD),
-			"wbawc"	| "th17"	=> Ok(Tags::WBaWC),
-			"sfw"	| "th17.5"	=> Ok(Tags::SFW),
-			"um"	| "th18"	=> Ok(Tags::UM),
-			"cbm"	| "th18.5"	=> Ok(Tags::CBM),
-			"udoalg"	| "th19"	=> Ok(Tags::UDoALG),
-			// Stages
-			"st1"	=> Ok(Tags::st1),
-			"st2"	=> Ok(Tags::st2),
-			"st3"	=> Ok(Tags::st3),
-			"st4"	=> Ok(Tags::st4),
-			"st5"	=> Ok(Tags::st5),
-			"st6"	=> Ok(Tags::st6),
-			"ex"	=> Ok(Tags::ex),
-			_	=> Err(()),
-		}
+		TAGSET.read().unwrap().iter()
+			.find(|a| a.key.eq_ignore_ascii_case(input) || a.aliases.iter().any(|b| b.eq_ignore_ascii_case(input)))
+			.map(|a| Tags(a.id))
+			.ok_or(())
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn stock_tags() {
+		let tags = parse(STOCK).unwrap();
+		assert!(tags.iter().any(|a| a.key == "EoSD" && a.category == "work" && a.aliases.contains(&"th06".to_string())));
+		assert!(tags.iter().all(|a| a.id < CUSTOM_BASE));
+	}
+
+	#[test]
+	fn fields_and_comments() {
+		let tags = parse("# comment\n\n1; Alpha; Work; The Alpha; 01 - Alpha; a1 first\n2; st1; stage; Stage 1\n").unwrap();
+		assert_eq!(tags.len(), 2);
+		assert_eq!((tags[0].id, tags[0].key.as_str(), tags[0].category.as_str()), (1, "Alpha", "work"));
+		assert_eq!(tags[0].exname, "01 - Alpha");
+		assert_eq!(tags[0].aliases, vec!["a1", "first"]);
+		assert!(tags[1].exname.is_empty() && tags[1].aliases.is_empty());
+	}
+
+	#[test]
+	fn bad_lines() {
+		assert_eq!(parse("1; Alpha; work").unwrap_err(), "line 1: expected `id; key; category; name`");
+		assert!(parse("x; Alpha; work; Alpha").unwrap_err().contains("is not an id"));
+		assert!(parse("1; -a; work; Alpha").unwrap_err().contains("can't be a key"));
+		assert_eq!(parse("1; a; work; A\n# two\n1; b; work; B").unwrap_err(), "line 3: the id is already used by a");
+		assert_eq!(parse("1; a; work; A\n2; A; work; B").unwrap_err(), "line 2: the key is already used by a");
+	}
+}
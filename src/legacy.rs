@@ -0,0 +1,75 @@
+// Tohorank: reading data files from older versions
+// Only the layout of the last release (1.0.0) is read here, anything older gets regenerated.
+
+use crate::{Chara, Glicko, Past, Match, data, groups::Tags};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::File;
+use std::path::Path;
+use serde::Deserialize;
+
+// Character, as of 1.0.0
+#[derive(Deserialize)]
+struct CharaV1 {
+    name:   String,
+    rank:   Glicko,
+    hist:   PastV1,
+    recent: VecDeque<Match>,
+    groups: HashSet<Tags>,
+    flags:  [bool; 4],
+}
+
+// Past records, as of 1.0.0
+#[derive(Deserialize)]
+struct PastV1 {
+    wins: usize,
+    loss: usize,
+    draw: usize,
+    old_rate: VecDeque<f64>,
+    old_rank: VecDeque<usize>,
+    peak_rate: Option<(f64, String)>,
+    peak_rank: Option<(usize, String)>,
+}
+
+// The flags of 1.0.0 were PC-98, nameless, not a girl, then don't know them
+fn named_flags(old: [bool; 4])
+-> (HashSet<String>, bool) {
+    let flags = ["pc98", "nameless", "notgirl"].iter()
+        .zip(old)
+        .filter(|a| a.1)
+        .map(|a| a.0.to_string())
+        .collect();
+    (flags, old[3])
+}
+
+impl From<CharaV1> for Chara {
+    fn from(old: CharaV1) -> Chara {
+        let (flags, hidden) = named_flags(old.flags);
+        Chara {
+            name: old.name,
+            rank: old.rank,
+            hist: Past {
+                wins: old.hist.wins,
+                loss: old.hist.loss,
+                draw: old.hist.draw,
+                old_rate: old.hist.old_rate,
+                old_rank: old.hist.old_rank,
+                peak_rate: old.hist.peak_rate,
+                peak_rank: old.hist.peak_rank,
+                timeline: Vec::new(),   // wasn't kept before
+            },
+            recent: old.recent,
+            groups: old.groups,
+            flags,
+            hidden,
+            retired: false,
+            meta: BTreeMap::new(),
+        }
+    }
+}
+
+// Try to read the data file as an older version, None if it isn't one either
+pub fn read(data_path: &Path)
+-> Option<Vec<Chara>> {
+    let old: Vec<CharaV1> = data::decode(File::open(data_path).ok()?).ok()?;
+    Some(old.into_iter().map(Chara::from).collect())
+}
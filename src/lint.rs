@@ -0,0 +1,286 @@
+// Tohorank: checking a character list
+// Everything that would go wrong reading a list, with the line and column,
+// found before the list gets anywhere near the data file.
+
+use crate::{groups::Tags, meta, pack};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use colored::Colorize;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    Error,      // the list can't be used like this
+    Warning,    // something is left out
+    Note,       // worth a look, maybe fine
+}
+
+pub struct Problem {
+    line: usize,
+    column: usize,
+    level: Level,
+    text: String,
+}
+
+// Column of a byte in a line, counting characters from 1
+fn column(line: &str, byte: usize)
+-> usize {
+    line[..byte].chars().count() + 1
+}
+
+// Check a character list, lines are `name; tags; flag; flag; key=value...`
+pub fn check(bytes: &[u8])
+-> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+    let mut report = |line: usize, column: usize, level: Level, text: String| {
+        problems.push(Problem { line, column, level, text });
+    };
+    let mut names: HashMap<String, (String, usize)> = HashMap::new();  // lowercase name: name, line
+    let mut tagged: Vec<(usize, Vec<Tags>)> = Vec::new();
+
+    for (number, raw) in bytes.split(|a| *a == b'\n').enumerate() {
+        let number = number + 1;
+        let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+        let line = match std::str::from_utf8(raw) {
+            Ok(line) => line,
+            Err(e) => {
+                let column = String::from_utf8_lossy(&raw[..e.valid_up_to()]).chars().count() + 1;
+                report(number, column, Level::Error, "not UTF-8 text, save the list as UTF-8".to_string());
+                continue;
+            },
+        };
+        let line = match line.strip_prefix('\u{feff}') {
+            Some(rest) if number == 1 => {
+                report(number, 1, Level::Warning, "a byte order mark, save the list as UTF-8 without BOM".to_string());
+                rest
+            },
+            _ => line,
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // characters that break the fields
+        for (byte, c) in line.char_indices() {
+            if c == '\t' {
+                report(number, column(line, byte), Level::Warning, "a tab, use spaces".to_string());
+            } else if c.is_control() {
+                report(number, column(line, byte), Level::Error, format!("a control character (U+{:04X})", c as u32));
+            } else if c.is_whitespace() && c != ' ' {
+                report(number, column(line, byte), Level::Warning, format!("an unusual space (U+{:04X}), use a plain space", c as u32));
+            }
+        }
+        for (byte, _) in line.match_indices(';') {
+            if !line[byte + 1..].starts_with(' ') {
+                report(number, column(line, byte), Level::Error, "`;` without a space after it, fields are separated by \"; \"".to_string());
+            }
+        }
+        let mut fields: Vec<(usize, &str)> = Vec::new();
+        let mut start = 0;
+        for (byte, _) in line.match_indices("; ") {
+            fields.push((start, &line[start..byte]));
+            start = byte + 2;
+        }
+        fields.push((start, &line[start..]));
+
+        // the name
+        let name = fields[0].1;
+        if name.trim().is_empty() {
+            report(number, 1, Level::Error, "no name, expected `name; tags; flags`".to_string());
+        } else if name != name.trim() {
+            report(number, 1, Level::Warning, format!("spaces around the name \"{}\"", name.trim()));
+        }
+        if !name.trim().is_empty() {
+            match names.get(&name.trim().to_lowercase()) {
+                Some((other, at)) if other == name.trim() => {
+                    report(number, 1, Level::Error, format!("\"{}\" is already on line {}", other, at));
+                },
+                Some((other, at)) => {
+                    report(number, 1, Level::Warning, format!("\"{}\" looks like \"{}\" on line {}", name.trim(), other, at));
+                },
+                None => { names.insert(name.trim().to_lowercase(), (name.trim().to_string(), number)); },
+            }
+        }
+
+        // the tags
+        let mut tags: Vec<Tags> = Vec::new();
+        if let Some((start, field)) = fields.get(1) {
+            for (byte, word) in words(field) {
+                let at = column(line, start + byte);
+                if let Ok(tag) = Tags::from_str(word) {
+                    if tags.contains(&tag) {
+                        report(number, at, Level::Warning, format!("{} twice", tag.key()));
+                    }
+                    tags.push(tag);
+                } else if pack::flag_exact(word).is_some() {
+                    report(number, at, Level::Warning, format!("{} is a flag, flags go after the tags: `name; tags; {}`", word, word));
+                } else {
+                    report(number, at, Level::Warning, format!("unknown tag {}, it's left out", word));
+                }
+            }
+        }
+
+        // the flags and metadata
+        let mut flags: Vec<&str> = Vec::new();
+        let mut facts: Vec<String> = Vec::new();
+        for (start, flag) in fields.iter().skip(2) {
+            let at = column(line, *start);
+            if flag.trim().is_empty() {
+                report(number, at, Level::Warning, "an empty field".to_string());
+            } else if let Some((key, value)) = meta::parse(flag) {
+                if meta::title(&key).is_none() {
+                    report(number, at, Level::Warning, format!("unknown field {}, it's one of {}", key,
+                        meta::FIELDS.iter().map(|a| a.0).collect::<Vec<&str>>().join(", ")));
+                } else if value.is_empty() {
+                    report(number, at, Level::Warning, format!("{} without a value", key));
+                } else if facts.contains(&key) {
+                    report(number, at, Level::Warning, format!("{} twice, the last one counts", key));
+                }
+                facts.push(key);
+            } else if pack::flag_exact(flag).is_some() {
+                if flags.contains(flag) {
+                    report(number, at, Level::Warning, format!("{} twice", flag));
+                }
+                flags.push(flag);
+            } else if let Some(known) = pack::flag(flag.trim()).or_else(|| pack::flag(&flag.trim().to_lowercase())) {
+                report(number, at, Level::Warning, format!("unknown flag {}, did you mean {}?", flag, known.key));
+            } else if Tags::from_str(flag).is_ok() {
+                report(number, at, Level::Warning, format!("{} is a tag, tags go in the second field", flag));
+            } else {
+                report(number, at, Level::Warning, format!("unknown flag {}, it's left out", flag));
+            }
+        }
+        tagged.push((number, tags));
+    }
+
+    // a stage tag is missing if nearly everyone else in one of their works has one
+    let staged = |tags: &Vec<Tags>| tags.iter().any(|a| a.category() == "stage");
+    let mut works: HashMap<Tags, (usize, usize)> = HashMap::new(); // everyone, with a stage
+    for (_, tags) in tagged.iter() {
+        for work in tags.iter().filter(|a| a.is_series_tag()) {
+            let count = works.entry(work.clone()).or_insert((0, 0));
+            count.0 += 1;
+            count.1 += staged(tags) as usize;
+        }
+    }
+    for (number, tags) in tagged.iter().filter(|a| !staged(&a.1)) {
+        let work = tags.iter()
+            .filter(|a| a.is_series_tag())
+            .find(|a| works.get(a).is_some_and(|&(all, with)| all > 5 && with * 10 >= (all - 1) * 9));
+        if let Some(work) = work {
+            report(*number, 1, Level::Note, format!("no stage tag, when nearly everyone else in {} has one", work.key()));
+        }
+    }
+    problems.sort_by_key(|a| (a.line, a.column));
+    problems
+}
+
+// The words of a field and where they start
+fn words(field: &str)
+-> Vec<(usize, &str)> {
+    let mut result: Vec<(usize, &str)> = Vec::new();
+    let mut start: Option<usize> = None;
+    for (byte, c) in field.char_indices().chain(std::iter::once((field.len(), ' '))) {
+        match (start, c == ' ') {
+            (None, false) => { start = Some(byte); },
+            (Some(from), true) => {
+                result.push((from, &field[from..byte]));
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    result
+}
+
+pub fn has_errors(problems: &[Problem])
+-> bool {
+    problems.iter().any(|a| a.level == Level::Error)
+}
+
+// Print the problems like a compiler does, `file:line:column: level: text`
+pub fn show(problems: &[Problem], source: &str, notes: bool) {
+    for problem in problems.iter().filter(|a| notes || a.level != Level::Note) {
+        let level = match problem.level {
+            Level::Error => "error".red().bold(),
+            Level::Warning => "warning".yellow(),
+            Level::Note => "note".cyan(),
+        };
+        println!("{}:{}:{}: {}: {}", source, problem.line, problem.column, level, problem.text);
+    }
+}
+
+// The `lint` command, the pack's list unless a file is given
+pub fn command(line: &str) {
+    let path = match line.trim().split_once(' ') {
+        Some((_, file)) => PathBuf::from(file.trim()),
+        None => pack::get().list_path(),
+    };
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("Couldn't read \"{}\": {}", path.display(), e);
+            return;
+        },
+    };
+    let problems = check(&bytes);
+    if problems.is_empty() {
+        println!("{}: no problems found.", path.display());
+        return;
+    }
+    show(&problems, &path.display().to_string(), true);
+    let count = |level: Level| problems.iter().filter(|a| a.level == level).count();
+    println!("\n{} errors, {} warnings, {} notes.", count(Level::Error), count(Level::Warning), count(Level::Note));
+    if has_errors(&problems) {
+        println!("The data file can't be updated from this list until the errors are fixed.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (line, column, level) of every problem
+    fn found(text: &[u8])
+    -> Vec<(usize, usize, Level)> {
+        crate::test_pack();
+        check(text).iter().map(|a| (a.line, a.column, a.level)).collect()
+    }
+
+    #[test]
+    fn clean_list() {
+        assert!(found(b"# comment\n\nCirno; EoSD st2\nRumia; EoSD st1; species=Youkai\n").is_empty());
+    }
+
+    #[test]
+    fn lines_and_columns() {
+        let text = "# comment\nCirno; EoSD st2\n\nRumia;EoSD\nDaiyousei; EoSD bogus st2\nCirno; EoSD st2\n";
+        assert_eq!(found(text.as_bytes()), vec![
+            (4, 6, Level::Error),       // `;` without a space
+            (5, 17, Level::Warning),    // unknown tag
+            (6, 1, Level::Error),       // Cirno again
+        ]);
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        // "Ä" is two bytes, the tab is still column 4
+        assert_eq!(found("Äb;\tc\n".as_bytes())[..2].iter().map(|a| (a.0, a.1)).collect::<Vec<_>>(), vec![(1, 3), (1, 4)]);
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(found(b"Cirno; EoSD\nRu\xffmia; EoSD\n"), vec![(2, 3, Level::Error)]);
+        assert_eq!(found("\u{feff}Cirno; EoSD\r\n".as_bytes()), vec![(1, 1, Level::Warning)]);
+    }
+
+    #[test]
+    fn flags_and_fields() {
+        let problems = found(b"Cirno; EoSD; pc9; EoSD; species=; colour=blue; pc98; pc98\n");
+        let columns: Vec<usize> = problems.iter().map(|a| a.1).collect();
+        assert_eq!(columns, vec![14, 19, 25, 35, 54]);
+        assert!(problems.iter().all(|a| a.0 == 1 && a.2 == Level::Warning));
+        assert!(has_errors(&check(b"; EoSD\n")));
+    }
+}
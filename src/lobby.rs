@@ -1,7 +1,8 @@
 // Tohorank: lobby functions, list and stats
 
 use colored::Colorize;
-use crate::{INCLUSIVE, DEVIATION_BAR, Chara, Tags, stats};
+use rand::rngs::ThreadRng;
+use crate::{INCLUSIVE, DEVIATION_BAR, Chara, Session, Tags, chart, glicko, groups, meta, pack, stats};
 
 // Show detailed stats about a character
 pub fn stat(chara: &Chara, touhous: &Vec<Chara>, full_rankings: bool) {
@@ -16,6 +17,17 @@ pub fn stat(chara: &Chara, touhous: &Vec<Chara>, full_rankings: bool) {
     );
     println!("{:-<1$}", "", 58);
 
+    // What the list says about them
+    if !chara.meta.is_empty() {
+        println!("==> {}", "PROFILE".bold());
+        for (key, title) in meta::FIELDS.iter() {
+            if let Some(value) = chara.meta.get(*key) {
+                println!("    {:<18}{}", title, value);
+            }
+        }
+        println!();
+    }
+
     // Rating information
     println!("==> {}", "RATING".bold());
     println!("{}",
@@ -97,6 +109,10 @@ pub fn stat(chara: &Chara, touhous: &Vec<Chara>, full_rankings: bool) {
         println!("    Highest rating: {:.0} on {}", prt, prt_time);
     }
 
+    // History charts
+    println!("\n==> {}", "HISTORY".bold());
+    chart::show_history(chara);
+
 
     // Rank informations
     println!("\n==> {}", "RANKINGS".bold());
@@ -104,7 +120,7 @@ pub fn stat(chara: &Chara, touhous: &Vec<Chara>, full_rankings: bool) {
     stats::print_rank_in_group(chara, no_tags, touhous);
     // All the other ranks
     if full_rankings {
-        for tag in chara.groups.iter() {
+        for tag in chara.tags().iter() {
             stats::print_rank_in_group(chara, vec![(tag.clone(), INCLUSIVE)], touhous);
         }
     } else {
@@ -222,7 +238,7 @@ pub fn list(mut touhous: Vec<&Chara>, first: usize, name_filter: &str) {
             #[allow(suspicious_double_ref_op)]
             touhous2.push(th.clone().clone()); // works
         }
-        for tag in touhou.groups.iter() {
+        for tag in touhou.tags().iter() {
             let group = stats::filter_group(vec![(tag.clone(), INCLUSIVE)], &touhous2);
             if stats::rank_in_group(touhou, &group).0 == 1 {
                 favorite = "★ ";
@@ -255,4 +271,269 @@ pub fn list(mut touhous: Vec<&Chara>, first: usize, name_filter: &str) {
         }
     }
     println!();
-}
\ No newline at end of file
+}
+
+// Show the head-to-head record of two characters
+pub fn versus(one: usize, two: usize, touhous: &[Chara], log: &[Session]) {
+    let (a, b) = (&touhous[one], &touhous[two]);
+    println!("{:-<1$}", "", 58);
+    println!("~~ {} vs {} ~~", a.name.bold(), b.name.bold());
+    println!("{:-<1$}", "", 58);
+
+    // Odds according to the current ratings
+    println!("==> {}", "RATINGS".bold());
+    let (mut mu, mut phi) = (a.rank.rate, a.rank.devi);
+    let (mut muj, mut phij) = (b.rank.rate, b.rank.devi);
+    glicko::glicko_two_scale(&mut mu, &mut phi);
+    glicko::glicko_two_scale(&mut muj, &mut phij);
+    let expected = glicko::e(&mu, &muj, &phij);
+    println!("    {:<26}{:.0} ± {:.0}", a.name, a.rank.rate, a.rank.devi * 1.96);
+    println!("    {:<26}{:.0} ± {:.0}", b.name, b.rank.rate, b.rank.devi * 1.96);
+    let gap = a.rank.rate - b.rank.rate;
+    let gap_devi = (a.rank.devi.powi(2) + b.rank.devi.powi(2)).sqrt();
+    println!("\n    Gap: {} ± {:.0}",
+        format!("{:+.0}", gap).bold(),
+        gap_devi * 1.96
+    );
+    println!("    {} is rated higher with {:.0}% confidence.",
+        if gap >= 0.0 { &a.name } else { &b.name },
+        100.0 * stats::normal_cdf(gap.abs() / gap_devi)
+    );
+    println!("    Expected to win: {} {:.0}% - {:.0}% {}",
+        a.name,
+        100.0 * expected,
+        100.0 * (1.0 - expected),
+        b.name
+    );
+
+    // Every battle between the two in the match log
+    println!("\n==> {}", "HEAD-TO-HEAD".bold());
+    let (mut wins, mut draws, mut loss, mut dislikes) = (0, 0, 0, 0);
+    for session in log.iter() {
+        for battle in session.records.iter() {
+            let side = if (battle.one, battle.two) == (one, two) {
+                1
+            } else if (battle.one, battle.two) == (two, one) {
+                2
+            } else {
+                continue;
+            };
+            let result = if battle.res == 0.5 {
+                draws += 1;
+                "Drew".white().bold()
+            } else if battle.res == 2.0 {
+                dislikes += 1;
+                "Disliked both".red().bold()
+            } else if (battle.res == 1.0) == (side == 1) {
+                wins += 1;
+                format!("{} won", a.name).blue().bold()
+            } else {
+                loss += 1;
+                format!("{} won", b.name).red().bold()
+            };
+            println!("    {:<22}{}", session.time, result);
+        }
+    }
+    let total = wins + draws + loss + dislikes;
+    if total == 0 {
+        println!("    They have never met.");
+    } else {
+        println!("\n    {} {}: {} {} - {} draws - {} {}{}",
+            total,
+            if total == 1 { "battle" } else { "battles" },
+            a.name,
+            wins,
+            draws,
+            loss,
+            b.name,
+            if dislikes > 0 {
+                format!(" ({} disliked both)", dislikes)
+            } else {
+                "".to_string()
+            }
+        );
+    }
+    println!();
+}
+
+// Show the chance of each character being #1, in the top n, and where they
+// land 90% of the time, by sampling the ratings many times
+pub fn probs(mut touhous: Vec<&Chara>, top: usize, name_filter: &str, rng: &mut ThreadRng) {
+    const ROUNDS: usize = 10000;
+    touhous.sort_by(|a, b| b.rank.rate.partial_cmp(&a.rank.rate).unwrap());
+    let counts = stats::simulate_ranks(&touhous, ROUNDS, rng);
+    let top = top.clamp(1, touhous.len());
+
+    println!("Sampled the rankings {} times.", ROUNDS);
+    println!("---------------------------------------------------------------");
+    println!("#    Name                        #1     Top {:<4}  90% interval ", top);
+    println!("---------------------------------------------------------------");
+    let mut shown = 0;
+    for (n, (touhou, count)) in touhous.iter().zip(counts.iter()).enumerate() {
+        if !touhou.name.to_lowercase().contains(&name_filter.to_lowercase()) {
+            continue;
+        }
+        let first = count[0] as f64 / ROUNDS as f64;
+        let in_top = count[..top].iter().sum::<usize>() as f64 / ROUNDS as f64;
+        // without a name filter, skip the ones outside the top with no real chance
+        if name_filter.is_empty() && n >= top && in_top < 0.005 {
+            continue;
+        }
+        // the 5th and 95th percentile of the ranks
+        let mut seen = 0;
+        let (mut low, mut high) = (1, 1);
+        for (rank, c) in count.iter().enumerate() {
+            if seen < ROUNDS / 20 {
+                low = rank + 1;
+            }
+            seen += c;
+            if seen < ROUNDS - ROUNDS / 20 {
+                high = rank + 2;
+            }
+        }
+        let entry = format!("{:<4} {:<26}{:>6.1}%  {:>6.1}%    #{} ~ #{}",
+            format!("{}.", n + 1),
+            touhou.name,
+            first * 100.0,
+            in_top * 100.0,
+            low,
+            high.min(touhous.len())
+        );
+        if in_top >= 0.9 {
+            println!("{}", entry.blue());
+        } else if in_top < 0.1 {
+            println!("{}", entry.truecolor(182, 185, 191));
+        } else {
+            println!("{}", entry);
+        }
+        shown += 1;
+    }
+    if shown == 0 {
+        println!("There's no one here... :(");
+    }
+    println!("\nⓘ {} for 90% chance or more, {} for less than 10%.\n",
+        "Blue".blue(),
+        "grey".truecolor(182, 185, 191)
+    );
+}
+
+// The number `groups` sorts by
+fn group_key<G>(group: &stats::GroupStats<G>, sort_by: &str)
+-> f64 {
+    match sort_by {
+        s if s.starts_with("med") => group.median,
+        s if s.starts_with("top") => group.top as f64,
+        s if s.starts_with("fav") => group.favorite,
+        _ => group.mean,
+    }
+}
+
+// A table of groups, in the order given
+fn group_table<G>(title: &str, list: &[(String, &stats::GroupStats<G>)]) {
+    println!("\n==> {}", title.bold());
+    println!("{:-<1$}", "", 84);
+    println!("#    Group                              Size    Mean  Median  Top 10     ★      ± ");
+    println!("{:-<1$}", "", 84);
+    for (n, (name, group)) in list.iter().enumerate() {
+        let entry = format!("{:<4} {:<34} {:>5}  {:>6.0}  {:>6.0}  {:>6}  {:>4.0}%  {:>4.0}",
+            format!("{}.", n + 1),
+            name.chars().take(34).collect::<String>(),
+            group.size,
+            group.mean,
+            group.median,
+            group.top,
+            group.favorite * 100.0,
+            group.devi
+        );
+        match n {
+            0 => { println!("{}", entry.truecolor(245, 212, 95)); },
+            1 => { println!("{}", entry.truecolor(180, 245, 212)); },
+            2 => { println!("{}", entry.truecolor(240, 140, 95)); },
+            _ => { println!("{}", entry); },
+        }
+    }
+}
+
+// What the tables are sorted by and how to change it
+fn groups_footer(sort_by: &str, command: &str) {
+    println!("\nⓘ Sorted by {}. Use `{} median`, `{} top` or `{} fav` to sort differently.",
+        match sort_by {
+            s if s.starts_with("med") => "median rating",
+            s if s.starts_with("top") => "top 10 presence",
+            s if s.starts_with("fav") => "share of favourites (★)",
+            _ => "mean rating",
+        },
+        command, command, command
+    );
+    println!("ⓘ ★: the share of the cast who are #1 in any of their groups.\n");
+}
+
+// Rank every work and stage by how much their cast is liked
+// sort_by: "median", "top", "fav", anything else sorts by the mean
+pub fn groups(touhous: &Vec<Chara>, sort_by: &str) {
+    let favorites: Vec<bool> = touhous.iter()
+        .map(|th| !th.dont_know() && stats::is_favorite(th, touhous))
+        .collect();
+    let mut all: Vec<stats::GroupStats<Tags>> = Tags::iter()
+        .filter_map(|tag| stats::group_stats(tag, touhous, &favorites))
+        .collect();
+    all.sort_by(|a, b| group_key(b, sort_by).partial_cmp(&group_key(a, sort_by)).unwrap());
+
+    for category in groups::categories() {
+        let list: Vec<(String, &stats::GroupStats<Tags>)> = all.iter()
+            .filter(|a| a.group.category() == category)
+            .map(|a| {
+                let name = if a.group.exname() != "" {
+                    format!("TH{} {}", a.group.exname().split(' ').next().unwrap_or(""), a.group.name())
+                } else {
+                    a.group.name().to_string()
+                };
+                (name, a)
+            })
+            .collect();
+        if !list.is_empty() {
+            group_table(&groups::category_title(&category), &list);
+        }
+    }
+    groups_footer(sort_by, "groups");
+}
+
+// Compare the values of some metadata, like youkai against humans: `groups by species`
+pub fn groups_by(touhous: &Vec<Chara>, line: &str) {
+    let mut words = line.split_whitespace();
+    let key = words.next().unwrap_or("").to_lowercase();
+    let sort_by = words.next().unwrap_or("");
+    let Some(title) = meta::title(&key) else {
+        println!("Usage: groups by [field] (median/top/fav), the fields are: {}",
+            meta::FIELDS.iter().map(|a| a.0).collect::<Vec<&str>>().join(", "));
+        return;
+    };
+    let favorites: Vec<bool> = touhous.iter()
+        .map(|th| !th.dont_know() && stats::is_favorite(th, touhous))
+        .collect();
+    // the same value in another case is the same group
+    let mut values: Vec<String> = Vec::new();
+    for value in touhous.iter().filter_map(|th| th.meta.get(&key)) {
+        if !values.iter().any(|a| a.eq_ignore_ascii_case(value)) {
+            values.push(value.clone());
+        }
+    }
+    let mut all: Vec<stats::GroupStats<String>> = values.into_iter()
+        .filter_map(|value| {
+            let member = |th: &Chara| th.meta.get(&key).is_some_and(|a| a.eq_ignore_ascii_case(&value));
+            stats::aggregate(value.clone(), member, touhous, &favorites)
+        })
+        .collect();
+    if all.is_empty() {
+        println!("Nobody in the {} list has a {}.", pack::get().noun, title.to_lowercase());
+        return;
+    }
+    all.sort_by(|a, b| group_key(b, sort_by).partial_cmp(&group_key(a, sort_by)).unwrap());
+    let list: Vec<(String, &stats::GroupStats<String>)> = all.iter().map(|a| (a.group.clone(), a)).collect();
+    group_table(&title.to_uppercase(), &list);
+    let missing = touhous.iter().filter(|th| !th.dont_know() && !th.meta.contains_key(&key)).count();
+    if missing > 0 {
+        println!("    {} {} without a {} are left out.", missing, pack::get().nouns(), title.to_lowercase());
+    }
+    groups_footer(sort_by, &format!("groups by {}", key));
+}
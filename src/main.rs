@@ -4,14 +4,14 @@
 use crate::data::update_data;
 use crate::groups::Tags;
 use std::fs::{self, File};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::{self, BufReader, Write};
 use std::str::FromStr;
+use std::time::Instant;
 use serde::{Deserialize, Serialize};
 use colored::Colorize;
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result, history::History};
-use strum::IntoEnumIterator;
 
 mod glicko;
 mod groups;
@@ -21,12 +21,31 @@ mod chara;
 mod sort;
 mod norm;
 mod lobby;
+mod summary;
+mod cycles;
+mod legacy;
+mod chart;
+mod tiers;
+mod export;
+mod report;
+mod reference;
+mod diff;
+mod dataset;
+mod pack;
+mod custom;
+mod lint;
+mod meta;
+mod serve;
 
 // Status returned by fight()
 enum FightCond {
     Next,
     Last,
-    Undo,
+    Undo(usize),    // how many steps to go back
+    Review,
+    Live,
+    Skip,
+    Forget(usize),  // global ID of a character marked "don't know" mid-battle
 }
 
 // Glicko ratings
@@ -47,6 +66,16 @@ struct Past {
     old_rank: VecDeque<usize>,
     peak_rate: Option<(f64, String)>,   // peak rating and time
     peak_rank: Option<(usize, String)>,
+    timeline: Vec<Snapshot>,            // every session, oldest first
+}
+
+// Where a character stood after a session
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Snapshot {
+    rate: f64,
+    devi: f64,
+    rank: usize,
+    time: String,
 }
 
 // Character
@@ -57,34 +86,79 @@ struct Chara {
     hist:   Past,               // historical stats
     recent: VecDeque<Match>,    // recent battles
     groups: HashSet<Tags>,      // groups this character belongs to
-    flags:  [bool; 4]           // True if: PC98, nameless, not a girl, don't know them
+    flags:  HashSet<String>,    // the pack's flags they have, like "pc98" or "nameless"
+    hidden: bool,               // True if: don't know them
                                 // use the methods for checks
+    retired: bool,              // True if: gone from the list, kept for the records
+    meta:   BTreeMap<String, String>,   // optional facts from the list, like "species"
 }
 
 impl Chara {
     // queries
-    fn is_pc98(&self) -> bool {
-        self.flags[0]
-    }
-    fn is_nameless(&self) -> bool {
-        self.flags[1]
+    // flags of the pack, by key
+    fn has_flag(&self, key: &str) -> bool {
+        self.flags.contains(key)
     }
-    fn is_not_girl(&self) -> bool {
-        self.flags[2]
-    }
-    // characters marked "don't know" are hidden in rankings
+    // characters marked "don't know" are hidden in rankings, and so are retired ones
     fn dont_know(&self) -> bool {
-        self.flags[3]
+        self.hidden || self.retired
     }
     fn toggle_dont_know(&mut self) {
-        self.flags[3] = !self.flags[3];
+        self.hidden = !self.hidden;
     }
-    // tag filtering
+    // tag filtering, the user's own tags included
     fn has_tag(&self, tag: &Tags) -> bool {
-        self.groups.contains(tag)
+        self.groups.contains(tag) || groups::is_member(tag, &self.name)
+    }
+    // every tag of the character, the user's own ones last
+    fn tags(&self) -> Vec<Tags> {
+        self.groups.iter()
+            .cloned()
+            .chain(Tags::iter().filter(|tag| tag.is_custom() && groups::is_member(tag, &self.name)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Chara {
+    // a character with nothing but a name and a rating, for tests
+    fn rated(name: &str, rate: f64, devi: f64) -> Chara {
+        Chara {
+            name: name.to_string(),
+            rank: Glicko { rate, devi, vola: 0.06 },
+            hist: Past {
+                wins: 0,
+                loss: 0,
+                draw: 0,
+                old_rate: VecDeque::new(),
+                old_rank: VecDeque::new(),
+                peak_rate: None,
+                peak_rank: None,
+                timeline: Vec::new(),
+            },
+            recent: VecDeque::new(),
+            groups: HashSet::new(),
+            flags: HashSet::new(),
+            hidden: false,
+            retired: false,
+            meta: BTreeMap::new(),
+        }
     }
 }
 
+// The stock pack and tags, loaded once for every test that needs them
+#[cfg(test)]
+fn test_pack() {
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    ONCE.call_once(|| {
+        colored::control::set_override(false);
+        let base = std::env::temp_dir().join(format!("tohorank-test-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        pack::load(&base.join("data.bin"), None, &base).unwrap();
+        groups::load(pack::get());
+    });
+}
+
 // A matchup between two characters
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Match {
@@ -93,6 +167,24 @@ struct Match {
     res: f32,
 }
 
+// A finished session, as kept in the match log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Session {
+    time: String,       // when it ended
+    secs: u64,          // how long it took
+    records: Vec<Match>,
+    summary: String,    // the summary shown at the end
+}
+
+// A ranking to compare with, kept in the profile
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Reference {
+    name: String,                   // what `compare` calls it
+    source: String,                 // the file it came from
+    added: String,
+    ranks: Vec<(String, usize)>,    // names as in our data, and their rank in it
+}
+
 const INCLUSIVE: bool = true; // for handling tags
 const DEVIATION_BAR: f64 = 160.0; // threshold for "high deviation"
 
@@ -101,21 +193,70 @@ fn lobby_help() {
     println!("-- 'list':    show the ranking list.");
     println!("-- 'stat':    see stats of a character.");
     println!("   'stat!':   even more stats!");
+    println!("-- 'tiers':   make a tier list.");
+    println!("-- 'export-image': save the ranking or tier list as SVG/PNG.");
+    println!("-- 'report':  save everything as a standalone HTML page.");
+    println!("-- 'portraits': set the folder of portraits for exports, or 'portraits off'.");
+    println!("-- 'groups':  rank the works and stages, or 'groups by species' and other metadata.");
+    println!("-- 'probs':   chances of each rank, given the uncertainty.");
+    println!("-- 'vs':      head-to-head record of two characters, 'vs reimu, kirisame marisa'.");
+    println!("-- 'inconsistencies': find contradictory picks.");
+    println!("-- 'compare': compare with another ranking.");
+    println!("-- 'diff':    compare profiles, sessions or rating methods.");
     println!("-------------------------------------");
     println!("-- 'reset':   reset the stats of a character.");
     println!("-- 'know':    hide/unhide a character in rankings.");
     println!("-- 'update':  updates the data file");
+    println!("-- 'import-dataset': update the characters and tags from tohosort's data.js.");
+    println!("-- 'init':    write out the stock character list and tags, to edit them.");
+    println!("-- 'lint':    check a character list for mistakes, the pack's list by default.");
     println!("-- 'help':    display this message.");
     println!("-- 'tags':    display a list of filters");
+    println!("-- 'tag':     add your own tags to characters.");
     println!("-- 'exit':    See you next time.");
 }
 
 fn main()
 -> Result<()> {
     let mut rng = rand::thread_rng();
-    let mut data_path = dirs::home_dir().expect("Home directory");
-    data_path.push(".tohorank");
+    // tohorank [serve --port N] [--profile name] [--pack folder]
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let serving = args.first().is_some_and(|a| a == "serve");
+    if serving {
+        args.remove(0);
+    }
+    let options = if serving { vec!["--profile", "--pack", "--port"] } else { vec!["--profile", "--pack"] };
+    if args.len() % 2 == 1 || args.chunks(2).any(|a| !options.contains(&a[0].as_str())) {
+        println!("Usage: tohorank [serve --port N] [--profile name] [--pack folder]");
+        println!("ⓘ A profile is bound to the pack it's first opened with, the Touhou one if none.");
+        println!("ⓘ `serve` sorts in the browser instead of the lobby, on port 8080 unless told otherwise.");
+        return Ok(());
+    }
+    let arg = |name: &str| args.chunks(2).find(|a| a[0] == name).map(|a| a[1].clone());
+    let port: u16 = match arg("--port").map(|a| a.parse()) {
+        None => 8080,
+        Some(Ok(port)) => port,
+        Some(Err(_)) => {
+            println!("The port is a number up to 65535.");
+            return Ok(());
+        },
+    };
+    let mut base = dirs::home_dir().expect("Home directory");
+    base.push(".tohorank");
+    let mut data_path = match arg("--profile") {
+        Some(name) => base.join("profiles").join(name),
+        None => base.clone(),
+    };
+    let _ = fs::create_dir_all(&data_path);
     data_path.push("data.bin");
+    // the pack comes first, it says where the lists are
+    if let Err(e) = pack::load(&data_path, arg("--pack").as_deref().map(std::path::Path::new), &base) {
+        println!("Couldn't load the pack! ({})", e);
+        return Ok(());
+    }
+    // then the tags, the stock list refers to them
+    groups::load(pack::get());
+    custom::load(&data_path);
     // open the data file
     let data_file = match File::open(&data_path) {
         Ok(file) => file,
@@ -125,34 +266,47 @@ fn main()
             File::open(&data_path).unwrap() // surely can't be worse
         }
     };
-    let reader = BufReader::new(data_file);
     // read all the touhous into memory
-    let mut touhous: Vec<Chara> = match bincode::deserialize_from(reader) {
+    let mut touhous: Vec<Chara> = match data::decode(data_file) {
         Ok(ths) => ths,
         Err(_) => {
-            println!("Data file not good! Creating a new one...");
             let mut backup_path = data_path.clone();
             backup_path.pop();
             backup_path.push("data.bin.bak");
             let _ = fs::copy(&data_path, &backup_path);
-            println!("The original file saved at '{}'", backup_path.display());
-            data::generate_data(&data_path);
-            let data_file_again = File::open(&data_path).unwrap();
-            let reader_again = BufReader::new(data_file_again);
-            bincode::deserialize_from(reader_again).unwrap() // surely can't be worse
+            match legacy::read(&data_path) {
+                Some(upgraded) => {
+                    println!("Data file is from an older version, upgrading...");
+                    println!("The original file saved at '{}'", backup_path.display());
+                    data::write_data(&upgraded, &data_path);
+                    upgraded
+                },
+                None => {
+                    println!("Data file not good! Creating a new one...");
+                    println!("The original file saved at '{}'", backup_path.display());
+                    data::generate_data(&data_path);
+                    let data_file_again = File::open(&data_path).unwrap();
+                    let reader_again = BufReader::new(data_file_again);
+                    bincode::deserialize_from(reader_again).unwrap() // surely can't be worse
+                },
+            }
         }
     };
     let souls_onboard = touhous.len();
     let mut records: Vec<Match> = Vec::new();
 
-    println!("Reading data file complete, got {} chracters.", souls_onboard);
-    update_data(&mut touhous, &data_path); // why not auto-update
+    println!("Reading data file complete, got {} {}.", souls_onboard, pack::get().nouns());
+    update_data(&mut touhous, &data_path, !serving); // why not auto-update
+    if serving {
+        serve::run(port, &mut touhous, &data_path);
+        return Ok(());
+    }
 
     let mut history_path = data_path.clone();
     history_path.pop();
     history_path.push("history.txt");
 
-    println!("=========~ Tohorank: Lobby ~=========");
+    println!("=========~ {}: Lobby ~=========", pack::get().title);
     lobby_help();
 
     let mut rl = DefaultEditor::new()?;
@@ -170,7 +324,7 @@ fn main()
                         None => String::from_str("").unwrap(),
                     };
                     // indices: global ID of participants (relative to the entire character vector)
-                    let (mut participants, indices) = sort::bouncer(filter_str, &mut touhous);
+                    let (mut participants, mut indices) = sort::bouncer(filter_str, &mut touhous);
                     if participants.len() < 2 {
                         println!("Cannot start with fewer than 2 participants!");
                         continue;
@@ -182,72 +336,148 @@ fn main()
                     // so we don't keep picking them (the record is only written after this session ends)
                     let mut picks: HashSet<usize> = HashSet::with_capacity(participants.len());
                     let mut pair_id = sort::matchmake(&mut rng, &participants, &mut picks);
+                    let started = Instant::now();
+                    // show provisional ratings after every pick?
+                    let mut live = false;
+                    // names by global ID, for reviewing the picks
+                    let roster: HashMap<usize, String> = indices.iter()
+                        .zip(participants.iter())
+                        .map(|(id, th)| (*id, th.name.clone()))
+                        .collect();
                     loop {
                         let (one, two) = chara::summon(&mut participants, &pair_id[0], &pair_id[1]);
                         match sort::fight(&mut records, one, two, indices[pair_id[0]], indices[pair_id[1]]) {
                             FightCond::Next => {
+                                if live {
+                                    sort::show_live(&participants, &indices, &records);
+                                }
                                 pair_id = sort::matchmake(&mut rng, &participants, &mut picks);
                             },
-                            FightCond::Undo => {
+                            FightCond::Live => {
+                                live = !live;
+                                println!("Live view {}.", if live { "on" } else { "off" });
+                                if live {
+                                    sort::show_live(&participants, &indices, &records);
+                                }
+                            },
+                            FightCond::Undo(steps) => {
+                                // replay the earliest of the undone battles
+                                let replay = records.len() - steps;
                                 // map global id (in records) -> participant id (for summon)
-                                let (global_id1, global_id2) = (records.last().unwrap().one, records.last().unwrap().two);
-                                let participant_id1 = indices.iter().position(|a| *a == global_id1).unwrap();
-                                let participant_id2 = indices.iter().position(|a| *a == global_id2).unwrap();
-                                pair_id = vec![participant_id1, participant_id2];
-                                records.pop();
+                                let (global_id1, global_id2) = (records[replay].one, records[replay].two);
+                                let participant_id1 = indices.iter().position(|a| *a == global_id1);
+                                let participant_id2 = indices.iter().position(|a| *a == global_id2);
+                                records.truncate(replay);
+                                match (participant_id1, participant_id2) {
+                                    (Some(p1), Some(p2)) => { pair_id = vec![p1, p2]; },
+                                    _ => {
+                                        // one of them has left the session, can't replay that one
+                                        println!("(That battle had someone who is no longer here.)");
+                                        pair_id = sort::matchmake(&mut rng, &participants, &mut picks);
+                                    },
+                                }
+                            },
+                            FightCond::Review => {
+                                sort::review(&mut records, &roster);
+                            },
+                            FightCond::Skip => {
+                                pair_id = sort::matchmake(&mut rng, &participants, &mut picks);
+                            },
+                            FightCond::Forget(global_id) => {
+                                sort::kick(&mut participants, &mut indices, &mut picks, global_id);
+                                if participants.len() < 2 {
+                                    println!("Not enough participants left, ending the session...");
+                                    sort::tally(&mut touhous, &mut records, started, &data_path);
+                                    break;
+                                }
+                                pair_id = sort::matchmake(&mut rng, &participants, &mut picks);
                             },
                             FightCond::Last => {
-                                glicko::calc(&mut touhous, &records);
-                                data::write_data(&touhous, &data_path);
-                                records.clear();
-                                println!("Data saved! Returning to lobby...");
+                                sort::tally(&mut touhous, &mut records, started, &data_path);
                                 break;
                             },
                         }
                     }
+                } else if line == "lint" || line.starts_with("lint ") {
+                    lint::command(&line);
                 } else if line.starts_with("l") {
                     // list!
-                    let mut how_many = 25;
-                    let mut name_filter = "".to_owned();
-                    let mut tags_filter = "".to_owned();
-                    for token in line.trim().split(" ").skip(1) {
-                        if token.parse::<usize>().is_ok() {
-                            how_many = token.parse().unwrap();  // is a number
-                        } else {
-                            // check if it is a tag
-                            let token_unsigned = if token.starts_with("-") {
-                                &token[1..]
-                            } else {
-                                &token[..]
-                            };
-                            match Tags::from_str(token_unsigned) {
-                                Ok(_) => {
-                                    // is a tag, add it to filter
-                                    tags_filter.push_str(&(token.to_string() + " "));
-                                },
-                                Err(_) => {
-                                    if token_unsigned.contains("pc98")
-                                       || token_unsigned.contains("notgirl")
-                                       || token_unsigned.contains("nameless")
-                                    {
-                                        tags_filter.push_str(&(token.to_string() + " "));
-                                    } else {
-                                        // is not a flag, treat as name
-                                        name_filter.push_str(&(token.to_string() + " "));
-                                    }
-                                },
-                            }
-                        }
-                    }
+                    let (number, tags_filter, name_filter) = sort::split_args(&line);
+                    let how_many = number.unwrap_or(25);
                     // drop the indices since we don't need it here
                     let (invited, _) = sort::bouncer(tags_filter, &mut touhous);
-                    if invited.len() == 0 {
+                    if invited.is_empty() {
                         println!("There's no one here... :(");
                         continue;
                     }
                     let invited_immutable: Vec<&Chara> =
                         invited.into_iter().map(|a| &*a).collect();
                     lobby::list(invited_immutable, how_many, name_filter.trim());
+                } else if line.starts_with("tier") {
+                    // tier list
+                    let (method, tags_filter) = tiers::parse_args(&line);
+                    let (invited, _) = sort::bouncer(tags_filter, &mut touhous);
+                    let invited: Vec<&Chara> = invited.into_iter().map(|a| &*a).collect();
+                    if invited.iter().all(|a| a.dont_know()) {
+                        println!("There's no one here... :(");
+                        continue;
+                    }
+                    tiers::show(invited, &method);
+                } else if line.starts_with("export-image") {
+                    // rankings as an image
+                    let args: Vec<&str> = line.trim().split(" ").collect();
+                    let Some(path) = args.get(1) else {
+                        println!("Usage: export-image [file.svg|file.png] (tiers [method]) [number] [filters]");
+                        continue;
+                    };
+                    let rest = args[2..].join(" ");
+                    let portraits = export::portraits_path(&data_path);
+                    let (svg, what) = if rest.starts_with("tier") {
+                        let (method, tags_filter) = tiers::parse_args(&rest);
+                        let (invited, _) = sort::bouncer(tags_filter, &mut touhous);
+                        let invited: Vec<&Chara> = invited.into_iter().map(|a| &*a).collect();
+                        if invited.iter().all(|a| a.dont_know()) {
+                            println!("There's no one here... :(");
+                            continue;
+                        }
+                        (export::tiers_svg(invited, &method, portraits.as_deref()), "Tier list")
+                    } else {
+                        let (number, tags_filter, _) = sort::split_args(&format!("export {}", rest));
+                        let (invited, _) = sort::bouncer(tags_filter, &mut touhous);
+                        let invited: Vec<&Chara> = invited.into_iter().map(|a| &*a).collect();
+                        if invited.iter().all(|a| a.dont_know()) {
+                            println!("There's no one here... :(");
+                            continue;
+                        }
+                        (export::list_svg(invited, number.unwrap_or(25), portraits.as_deref()), "Ranking")
+                    };
+                    match export::save(&svg, std::path::Path::new(path)) {
+                        Ok(_) => { println!("{} saved to '{}'.", what, path); },
+                        Err(e) => { println!("Couldn't export: {}", e); },
+                    }
+                } else if line == "portraits" || line.starts_with("portraits ") {
+                    // where export-image and report find portraits
+                    export::portraits_command(&line, &data_path);
+                } else if line.starts_with("group") {
+                    // group aggregates
+                    let sort_by = line.trim().split_once(" ").map(|a| a.1.trim()).unwrap_or("");
+                    match sort_by.strip_prefix("by") {
+                        Some(rest) if rest.is_empty() || rest.starts_with(' ') => { lobby::groups_by(&touhous, rest); },
+                        _ => { lobby::groups(&touhous, sort_by); },
+                    }
+                } else if line.starts_with("prob") {
+                    // rank probabilities
+                    let (number, tags_filter, name_filter) = sort::split_args(&line);
+                    let (invited, _) = sort::bouncer(tags_filter, &mut touhous);
+                    let invited: Vec<&Chara> = invited.into_iter()
+                        .map(|a| &*a)
+                        .filter(|a| !a.dont_know())
+                        .collect();
+                    if invited.len() < 2 {
+                        println!("There's no one here... :(");
+                        continue;
+                    }
+                    lobby::probs(invited, number.unwrap_or(10), name_filter.trim(), &mut rng);
                 } else if line.starts_with("stat") {
                     // stat!
                     match line.split_once(" ") {
@@ -261,49 +491,61 @@ fn main()
                         }
                         None => { println!("Usage: stat [character]"); },
                     }
-                } else if line.starts_with("n") {
-                    // compare rankings
-                    let everyone = sort::bouncer("".to_string(), &mut touhous).0
-                        .into_iter()
-                        .map(|a| &*a)
-                        .collect();
-                    let list = norm::build(everyone, 0);
-                    let correlation = norm::wrc_calc(&list, 2);
-                    println!("Weighted Rank Correlation = {}",
-                        format!("{:.3}", correlation).bold()
-                    );
-                    println!("The rankings {} {}.\n",
-                        match correlation.abs() {
-                            0.0 => "are ",
-                            1.0 => "perfectly",
-                            0.0..=0.2 => "weakly",
-                            0.2..=0.4 => "",
-                            0.4..=0.6 => "roughly",
-                            0.6..=0.8 => "mostly",
-                            0.8..=1.0 => "strongly",
-                            _ => "make "
-                        },
-                        if correlation == 0.0 {
-                            "not correlated"
-                        } else if correlation.abs() > 1.0 {
-                            "no sense"
-                        } else if correlation < 0.0 {
-                            "disagree"
-                        } else {
-                            "agree"
+                } else if line.starts_with("vs") {
+                    // head-to-head
+                    let usage = "Usage: vs [character], [character] (or [character] vs [character])";
+                    let Some((_, names)) = line.trim().split_once(" ") else {
+                        println!("{}", usage);
+                        continue;
+                    };
+                    // names have spaces, so a single space only separates two one-word names
+                    let one_word_each = names.split_whitespace().count() == 2;
+                    let split = names.split_once(",")
+                        .or(names.split_once(" vs "))
+                        .or(names.split_once(" ").filter(|_| one_word_each));
+                    let Some((name1, name2)) = split else {
+                        println!("{}", usage);
+                        continue;
+                    };
+                    let mut ids = Vec::with_capacity(2);
+                    for name in [name1.trim(), name2.trim()] {
+                        match chara::find(&touhous, name.to_string()) {
+                            Some(th) => { ids.push(touhous.iter().position(|a| a.name == th.name).unwrap()); },
+                            None => { println!("Character \"{}\" not found!", name); },
                         }
-                    );
-
-                    println!("--- More stats ---");
-                    let correlation_unweighted = norm::wrc_calc(&list, 1);
-                    println!("Unweighted (Spearman's Rho) = {:.3}", correlation_unweighted);
-                    let t_score = correlation
-                        * ((list.len() as f64 - 2.0) / (1.0 - correlation.powf(2.0))).sqrt();
-                    println!("t-score: {:.3}", t_score);
-
-                    norm::show_plot(&list);
+                    }
+                    if ids.len() < 2 {
+                        continue;
+                    }
+                    if ids[0] == ids[1] {
+                        println!("That's the same character twice!");
+                        continue;
+                    }
+                    lobby::versus(ids[0], ids[1], &touhous, &data::read_log(&data_path));
+                } else if line.starts_with("inc") {
+                    // contradictory picks
+                    cycles::show(&mut touhous, &data_path, &data::read_log(&data_path));
+                } else if line.starts_with("diff") {
+                    // compare two of our rankings
+                    diff::show(&line, &touhous, &data_path);
+                } else if line.starts_with("compare") {
+                    // compare with another ranking
+                    reference::command(&line, &touhous, &data_path);
                 } else if line.starts_with("h") {
                     lobby_help();
+                } else if line.starts_with("report") {
+                    // everything in one html page
+                    match line.trim().split_once(" ") {
+                        Some((_, path)) => {
+                            let log = data::read_log(&data_path);
+                            let portraits = export::portraits_path(&data_path);
+                            match report::write(&touhous, &log, std::path::Path::new(path.trim()), portraits.as_deref()) {
+                                Ok(_) => { println!("Report saved to '{}'.", path.trim()); },
+                                Err(e) => { println!("Couldn't write the report: {}", e); },
+                            }
+                        },
+                        None => { println!("Usage: report [file.html]"); },
+                    }
                 } else if line.starts_with("reset") {
                     match line.split_once(" ") {
                         Some((_, name)) => {
@@ -335,6 +577,9 @@ fn main()
                     match line.split_once(" ") {
                         Some((_, name)) => {
                             match chara::find_mut(&mut touhous, name.to_string()) {
+                                Some(th) if th.retired => {
+                                    println!("{} is retired, they're not in the {} list anymore.", th.name.bold(), pack::get().noun);
+                                },
                                 Some(th) => {
                                     th.toggle_dont_know();
                                     println!("{} will {}be hidden.",
@@ -352,22 +597,37 @@ fn main()
                         }
                         None => { println!("Usage: know [character]"); },
                     }
+                } else if line.trim() == "init" {
+                    data::init();
+                } else if line.starts_with("import-dataset") {
+                    dataset::import(&line, &mut touhous, &data_path);
                 } else if line.starts_with("update") {
-                    data::update_data(&mut touhous, &data_path);
+                    data::update_data(&mut touhous, &data_path, true);
+                } else if line == "tag" || line.starts_with("tag ") {
+                    custom::command(&line, &mut touhous, &data_path);
                 } else if line.starts_with("tags") {
                     println!("List of all filter tags:");
                     println!("Add '-' to the front to exclude them instead.");
-                    println!("You can also specify by the number like 'th06'\n");
-                    for tag in Tags::iter() {
-                        if tag.is_series_tag() {
-                            println!("{:<8}{} ~ {}", format!("{:?}", tag), tag.exname(), tag.name());
-                        } else {
-                            println!("{:<8}{}", format!("{:?}", tag), tag.name());
+                    if let Some(tag) = groups::all().iter().find(|a| !a.aliases.is_empty()) {
+                        println!("You can also specify by the number like '{}'", tag.aliases[0]);
+                    }
+                    println!();
+                    for category in groups::categories() {
+                        println!("==> {}", groups::category_title(&category).bold());
+                        for tag in Tags::iter().filter(|a| a.category() == category) {
+                            if tag.exname().is_empty() {
+                                println!("{:<8}{}", tag.key(), tag.name());
+                            } else {
+                                println!("{:<8}{} ~ {}", tag.key(), tag.exname(), tag.name());
+                            }
                         }
+                        println!();
+                    }
+                    println!("==> {}", "FLAGS".bold());
+                    for flag in pack::flags().iter() {
+                        println!("{:<10}{} ({} by default)", flag.key, flag.name, if flag.include { "included" } else { "excluded" });
                     }
-                    println!("pc98");
-                    println!("notgirl");
-                    println!("nameless");
+                    println!("\nⓘ `flag` lets them in, `-flag` keeps them out, `flag!` shows only them.");
                 } else if line.starts_with("e") {
                     break;
                 } else {
@@ -0,0 +1,69 @@
+// Tohorank: character metadata
+// Optional facts about a character, written in the character list as
+// `key=value` fields after the flags, like `species=Youkai`.
+// Filters take `species=youkai`, and `groups by species` compares them.
+
+use crate::{Chara, pack};
+use std::path::PathBuf;
+
+// Every field: the key, and how it's shown
+pub const FIELDS: [(&str, &str); 6] = [
+    ("species", "Species"),
+    ("affiliation", "Affiliation"),
+    ("debut", "First appearance"),
+    ("theme", "Theme"),
+    ("ability", "Ability"),
+    ("portrait", "Portrait"),
+];
+
+// How a field is shown, None if there's no such field
+pub fn title(key: &str)
+-> Option<&'static str> {
+    FIELDS.iter().find(|a| a.0 == key).map(|a| a.1)
+}
+
+// A field of the list, like "species=Youkai"
+pub fn parse(field: &str)
+-> Option<(String, String)> {
+    let (key, value) = field.split_once('=')?;
+    Some((key.trim().to_lowercase(), value.trim().to_string()))
+}
+
+// A filter word about a field: `species=youkai` lets only them in, `-species=human` keeps them out
+pub fn rule(token: &str)
+-> Option<(String, String, bool)> {
+    let (token, include) = match token.strip_prefix('-') {
+        Some(rest) => (rest, false),
+        None => (token, true),
+    };
+    let (key, value) = parse(token)?;
+    if title(&key).is_none() || value.is_empty() {
+        return None;
+    }
+    Some((key, value, include))
+}
+
+// Does the field have this value? Any case, and `_` for spaces, "scarlet_devil" is enough
+pub fn matches(chara: &Chara, key: &str, value: &str)
+-> bool {
+    let value = value.replace('_', " ").to_lowercase();
+    chara.meta.get(key).is_some_and(|a| a.to_lowercase().contains(&value))
+}
+
+// A line about a character for battles, like "Youkai · Scarlet Devil Mansion"
+pub fn brief(chara: &Chara)
+-> String {
+    ["species", "affiliation", "debut"].iter()
+        .filter_map(|a| chara.meta.get(*a))
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(" · ")
+}
+
+// The portrait file a character's list entry points at, relative to the list
+pub fn portrait(chara: &Chara)
+-> Option<PathBuf> {
+    let list = pack::get().list_path();
+    let dir = list.parent().unwrap_or(&pack::get().dir);
+    chara.meta.get("portrait").map(|a| dir.join(a))
+}
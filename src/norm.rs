@@ -9,88 +9,248 @@
 // A General Class of Weighted Rank Correlation Measures
 // arxiv.org/pdf/2001.07298
 
-use std::collections::HashMap;
-use std::io::{BufReader, BufRead};
-use crate::File;
+// Kendall's tau-b, Rank-Biased Overlap, top-k overlap and the footrule
+// are next to it, with p-values from random permutations.
+
+use std::collections::{HashMap, HashSet};
+use colored::Colorize;
+use rand::rngs::ThreadRng;
+use rand::seq::SliceRandom;
 use termplot::*;
 
 use crate::Chara;
 
-// Build the list containing the characters (sorted by rank)
-// and their ranks in the target rankings (to be compared to)
-// target does nothing for now
-pub fn build(mut touhous: Vec<&Chara>, _target: u8)
+// how many shuffles for the p-values
+const PERMUTATIONS: usize = 2000;
+// the top k in top-k overlap
+const TOP_K: usize = 10;
+// persistence of RBO
+const RBO_P: f64 = 0.9;
+
+// Build the list containing the characters in both rankings
+// and their ranks in ours and in the reference (to be compared to)
+// ties in the reference keep the order of the file
+pub fn build(mut touhous: Vec<&Chara>, reference: &[(String, usize)])
 -> HashMap<String, (usize, usize)> {
+    touhous.retain(|th| !th.dont_know());
+    touhous.sort_by(|a, b| b.rank.rate.partial_cmp(&a.rank.rate).unwrap());
+    let ours: Vec<String> = touhous.iter().map(|th| th.name.clone()).collect();
+    let mut theirs: Vec<&(String, usize)> = reference.iter().collect();
+    theirs.sort_by_key(|a| a.1);
+    let theirs: Vec<String> = theirs.into_iter().map(|a| a.0.clone()).collect();
+    pair_up(&ours, &theirs)
+}
 
-    // open the file (just testing for now)
-    let ref_list = File::open("./src/best_taste.txt").expect("Secret WIP feature");
+// Same from two orderings (best first), both are renumbered 1..n
+// over the characters in common
+pub fn pair_up(ours: &[String], theirs: &[String])
+-> HashMap<String, (usize, usize)> {
+    let theirs: Vec<&String> = theirs.iter().filter(|a| ours.contains(a)).collect();
+    let ours: Vec<&String> = ours.iter().filter(|a| theirs.contains(a)).collect();
+    let mut list: HashMap<String, (usize, usize)> = HashMap::with_capacity(ours.len());
+    for (n, name) in ours.iter().enumerate() {
+        let target = theirs.iter().position(|a| a == name).unwrap();
+        list.insert(name.to_string(), (n + 1, target + 1));
+    }
+    list
+}
 
-    let mut list: HashMap<String, (usize, usize)> = HashMap::with_capacity(touhous.len());
+// Print every measure side by side
+// ours and theirs are the full orderings (best first), for RBO which doesn't need
+// the same characters in both
+pub fn show(list: &HashMap<String, (usize, usize)>, ours: &[String], theirs: &[String], rng: &mut ThreadRng) {
+    if list.len() < 3 {
+        println!("Not enough characters in common to compare.\n");
+        return;
+    }
+    let pairs: Vec<(usize, usize)> = list.values().cloned().collect();
+    let n = pairs.len();
+    // with few in common the top k would be everyone
+    let k = TOP_K.min(n / 2);
 
-    // add all the characters to the list
-    // sort by rank
-    touhous.sort_by(|a, b| b.rank.rate.partial_cmp(&a.rank.rate).unwrap());
-    // set the rank in target to 0 for now
-    for (n, th) in touhous.iter().enumerate() {
-        list.insert(th.name.clone(), (n + 1, 0));
-    }
-
-    // add from the target list
-    let reader = BufReader::new(ref_list);
-    for line in reader.lines() {
-        match line {
-            Ok(l) => {
-                let line: Vec<&str> = l.split(".").map(|a| a.trim()).collect();
-                if !list.contains_key(line[1]) {
-                    // not in our rankings, skip
-                    println!("Note: Character {} is not in your ranking, skipping...", line[1]);
-                    continue;
-                }
-                // set the rank in target
-                list.entry(line[1].to_string()).and_modify(|a| (*a).1 = line[0].parse().unwrap());
-            }
-            Err(error) => {
-                eprintln!("\nError reading... {}", error);
-                break;
-            }
+    let correlation = wrc(&pairs, 2);
+    println!("Weighted Rank Correlation = {}",
+        format!("{:.3}", correlation).bold()
+    );
+    println!("The rankings {} {}.\n",
+        match correlation.abs() {
+            0.0 => "are ",
+            1.0 => "perfectly",
+            0.0..=0.2 => "weakly",
+            0.2..=0.4 => "",
+            0.4..=0.6 => "roughly",
+            0.6..=0.8 => "mostly",
+            0.8..=1.0 => "strongly",
+            _ => "make "
+        },
+        if correlation == 0.0 {
+            "not correlated"
+        } else if correlation.abs() > 1.0 {
+            "no sense"
+        } else if correlation < 0.0 {
+            "disagree"
+        } else {
+            "agree"
         }
+    );
+
+    // every measure, with how often random shuffles do as well
+    let footrule_max = (n * n / 2) as f64;
+    let measures: Vec<(&str, String, f64, &str)> = vec![
+        ("Weighted Spearman",
+            format!("{:.3}", correlation),
+            p_value(&pairs, correlation, |a| wrc(a, 2), true, rng),
+            "overall agreement, the top counts more"),
+        ("Spearman's rho",
+            format!("{:.3}", wrc(&pairs, 1)),
+            p_value(&pairs, wrc(&pairs, 1), |a| wrc(a, 1), true, rng),
+            "overall agreement, every rank counts the same"),
+        ("Kendall's tau-b",
+            format!("{:.3}", kendall(&pairs)),
+            p_value(&pairs, kendall(&pairs), kendall, true, rng),
+            "share of pairs in the same order, shrugs off a few big swaps"),
+        ("Rank-Biased Overlap",
+            format!("{:.3}", rbo(ours, theirs, RBO_P)),
+            rbo_p_value(ours, theirs, rng),
+            "the full lists, even with different characters, top-heavy"),
+        ("Top-k overlap",
+            format!("{}/{}", top_overlap(&pairs, k) as usize, k),
+            p_value(&pairs, top_overlap(&pairs, k), |a| top_overlap(a, k), true, rng),
+            "are the favorites the same?"),
+        ("Footrule distance",
+            format!("{:.0} ({:.2})", footrule(&pairs), footrule(&pairs) / footrule_max),
+            p_value(&pairs, footrule(&pairs), footrule, false, rng),
+            "total displacement, 0 is identical"),
+    ];
+    println!("{:<22}{:>12}{:>10}   Good for", "Measure", "Value", "p-value");
+    println!("{:-<1$}", "", 92);
+    for (name, value, p, good_for) in measures {
+        println!("{:<22}{:>12}{:>10}   {}",
+            name,
+            value,
+            if p < 0.001 { "< 0.001".to_string() } else { format!("{:.3}", p) },
+            good_for.truecolor(182, 185, 191)
+        );
     }
+    println!("\nⓘ {} characters in common, RBO looks at the top {} of both with p = {}.", n, ours.len().min(theirs.len()), RBO_P);
+    println!("ⓘ Spearman and Kendall want the same characters in both, use RBO when the lists differ a lot.");
+    println!("ⓘ A p-value is the share of {} random shuffles agreeing at least as well, small means it's no coincidence.", PERMUTATIONS);
+
+    show_plot(list);
+}
 
-    // delete character that isn't in the target list
-    list.retain(|_, rank| (*rank).1 != 0);
-
-    // recalculate the original ranks
-    let mut find = 1;
-    let mut replace = 1;
-    let mut found;
-    let mut was_found = true;
-
-    while replace <= list.len() {
-        found = false;
-        for i in list.values_mut() {
-            if i.0 == find {
-                if !was_found {
-                    i.0 = replace;
-                    find -= 1;
-                }
-                replace += 1;
-                found = true;
+// Kendall's tau-b over (our rank, their rank) pairs
+pub fn kendall(pairs: &[(usize, usize)])
+-> f64 {
+    let (mut concordant, mut discordant, mut tied_x, mut tied_y): (f64, f64, f64, f64) = (0.0, 0.0, 0.0, 0.0);
+    for (i, a) in pairs.iter().enumerate() {
+        for b in pairs[i + 1..].iter() {
+            let dx = a.0.cmp(&b.0);
+            let dy = a.1.cmp(&b.1);
+            if dx.is_eq() && dy.is_eq() {
+                continue;
+            } else if dx.is_eq() {
+                tied_x += 1.0;
+            } else if dy.is_eq() {
+                tied_y += 1.0;
+            } else if dx == dy {
+                concordant += 1.0;
+            } else {
+                discordant += 1.0;
             }
         }
-        was_found = found;
-        find += 1;
     }
+    let denominator = ((concordant + discordant + tied_x) * (concordant + discordant + tied_y)).sqrt();
+    if denominator == 0.0 {
+        0.0
+    } else {
+        (concordant - discordant) / denominator
+    }
+}
 
-    list
+// Extrapolated Rank-Biased Overlap of two orderings, down to the shorter one
+// p is the persistence, 0.9 puts ~86% of the weight on the top 10
+// From the paper:
+// A Similarity Measure for Indefinite Rankings (Webber, Moffat, Zobel 2010)
+pub fn rbo(ours: &[String], theirs: &[String], p: f64)
+-> f64 {
+    let depth = ours.len().min(theirs.len());
+    if depth == 0 {
+        return 0.0;
+    }
+    let mut seen_ours: HashSet<&str> = HashSet::with_capacity(depth);
+    let mut seen_theirs: HashSet<&str> = HashSet::with_capacity(depth);
+    let mut overlap = 0.0;
+    let mut sum = 0.0;
+    for d in 0..depth {
+        seen_ours.insert(&ours[d]);
+        if seen_theirs.contains(ours[d].as_str()) {
+            overlap += 1.0;
+        }
+        seen_theirs.insert(&theirs[d]);
+        if seen_ours.contains(theirs[d].as_str()) {
+            overlap += 1.0;
+        }
+        sum += overlap / (d + 1) as f64 * p.powi(d as i32 + 1);
+    }
+    overlap / depth as f64 * p.powi(depth as i32) + (1.0 - p) / p * sum
+}
+
+// How many characters are in the top k of both
+pub fn top_overlap(pairs: &[(usize, usize)], k: usize)
+-> f64 {
+    pairs.iter().filter(|a| a.0 <= k && a.1 <= k).count() as f64
+}
+
+// Spearman's footrule, the sum of rank differences
+pub fn footrule(pairs: &[(usize, usize)])
+-> f64 {
+    pairs.iter().map(|a| a.0.abs_diff(a.1)).sum::<usize>() as f64
+}
+
+// Share of shuffles of their ranks scoring at least as well as observed
+// higher says if a higher value means closer rankings
+fn p_value(pairs: &[(usize, usize)], observed: f64, measure: impl Fn(&[(usize, usize)]) -> f64, higher: bool, rng: &mut ThreadRng)
+-> f64 {
+    let mut theirs: Vec<usize> = pairs.iter().map(|a| a.1).collect();
+    let mut shuffled = pairs.to_vec();
+    let mut hits = 0;
+    for _ in 0..PERMUTATIONS {
+        theirs.shuffle(rng);
+        for (pair, rank) in shuffled.iter_mut().zip(theirs.iter()) {
+            pair.1 = *rank;
+        }
+        let value = measure(&shuffled);
+        if higher && value >= observed - 1e-12 || !higher && value <= observed + 1e-12 {
+            hits += 1;
+        }
+    }
+    // count the observed one too, a p-value of 0 isn't a thing
+    (hits + 1) as f64 / (PERMUTATIONS + 1) as f64
+}
+
+// Same as p_value() but shuffling their whole list, for RBO
+fn rbo_p_value(ours: &[String], theirs: &[String], rng: &mut ThreadRng)
+-> f64 {
+    let observed = rbo(ours, theirs, RBO_P);
+    let mut shuffled = theirs.to_vec();
+    let mut hits = 0;
+    for _ in 0..PERMUTATIONS {
+        shuffled.shuffle(rng);
+        if rbo(ours, &shuffled, RBO_P) >= observed - 1e-12 {
+            hits += 1;
+        }
+    }
+    (hits + 1) as f64 / (PERMUTATIONS + 1) as f64
 }
 
 // calculates the Weighted Rank Correlation
 // the p-value affacts how aggresive the weighting is
 // a value of 1 turns this into regular Spearman's Rho
-pub fn wrc_calc(list: &HashMap<String, (usize, usize)>, p: u32)
+pub fn wrc(pairs: &[(usize, usize)], p: u32)
 -> f64 {
 
-    let n = list.len();
+    let n = pairs.len();
 
     // kappa
     let kappa = |p: u32| -> f64 {
@@ -98,7 +258,7 @@ pub fn wrc_calc(list: &HashMap<String, (usize, usize)>, p: u32)
     };
 
     // intermediate stuff
-    let stuff = list.values()
+    let stuff = pairs.iter()
         .map(|i| i.1 * (n + 1 - i.0).pow(p) + i.0 * (n + 1 - i.1).pow(p))
         .sum::<usize>() as f64;
 
@@ -136,4 +296,59 @@ pub fn show_plot(list: &HashMap<String, (usize, usize)>) {
 
     // help text
     println!("ⓘ The graph appears \"sorted\" if the rankings agree perfectly.\n");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str])
+    -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn kendall_agrees_and_disagrees() {
+        let same: Vec<(usize, usize)> = (1..=5).map(|a| (a, a)).collect();
+        let reversed: Vec<(usize, usize)> = (1..=5).map(|a| (a, 6 - a)).collect();
+        assert!((kendall(&same) - 1.0).abs() < 1e-12);
+        assert!((kendall(&reversed) + 1.0).abs() < 1e-12);
+        // one swap out of 6 pairs: (5 - 1) / 6
+        let swapped = [(1, 2), (2, 1), (3, 3), (4, 4)];
+        assert!((kendall(&swapped) - 4.0 / 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn kendall_with_ties() {
+        // tau-b: 2 concordant, 0 discordant, 1 tied in x only
+        let tied = [(1, 1), (1, 2), (2, 3)];
+        assert!((kendall(&tied) - 2.0 / (2.0f64 * 3.0).sqrt()).abs() < 1e-12);
+        assert_eq!(kendall(&[(1, 1)]), 0.0);
+    }
+
+    #[test]
+    fn rbo_bounds() {
+        let ours = names(&["a", "b", "c", "d"]);
+        assert!((rbo(&ours, &ours, 0.9) - 1.0).abs() < 1e-12);
+        assert_eq!(rbo(&ours, &names(&["w", "x", "y", "z"]), 0.9), 0.0);
+        assert_eq!(rbo(&ours, &[], 0.9), 0.0);
+        // the top counts more than the bottom
+        let top_swapped = rbo(&ours, &names(&["b", "a", "c", "d"]), 0.9);
+        let bottom_swapped = rbo(&ours, &names(&["a", "b", "d", "c"]), 0.9);
+        assert!(top_swapped < bottom_swapped && bottom_swapped < 1.0);
+    }
+
+    #[test]
+    fn rbo_partial_lists() {
+        // only as deep as the shorter list
+        let ours = names(&["a", "b", "c", "d", "e"]);
+        assert!((rbo(&ours, &names(&["a", "b"]), 0.9) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn footrule_sums_differences() {
+        assert_eq!(footrule(&[(1, 1), (2, 2)]), 0.0);
+        assert_eq!(footrule(&[(1, 3), (2, 2), (3, 1)]), 4.0);
+        assert_eq!(top_overlap(&[(1, 3), (2, 2), (3, 1)], 2), 1.0);
+    }
+}
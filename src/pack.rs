@@ -0,0 +1,255 @@
+// Tohorank: dataset packs
+// A pack says what is being sorted: the character list, the tags, the flags
+// and what things are called. Each profile is bound to one pack, and the ones
+// that aren't use the Touhou pack built into the binary.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+// the stock pack, same format as pack.txt in a pack folder
+const STOCK: &str = include_str!("pack.txt");
+
+// A flag of the pack, like "pc98"
+#[derive(Clone)]
+pub struct Flag {
+    pub key: String,
+    pub name: String,       // "PC-98 duplicates"
+    pub include: bool,      // in the pool unless the filter says otherwise
+    pub aliases: Vec<String>,   // other words for it in filters
+}
+
+// What a filter does with a flag: `pc98`, `-pc98` or `pc98!`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rule {
+    In,     // let them in
+    Out,    // keep them out
+    Only,   // only them
+}
+
+pub struct Pack {
+    pub name: String,       // "Touhou Project"
+    pub title: String,      // "Tohorank", for the lobby and exports
+    pub noun: String,       // "character"
+    pub list: String,       // file name of the character list
+    pub dir: PathBuf,       // where the list, tags.txt and pack.txt are
+    pub stock: bool,        // the built-in one?
+}
+
+static PACK: OnceLock<Pack> = OnceLock::new();
+// the flags can grow while running, when a dataset brings new ones
+static FLAGS: RwLock<Vec<Flag>> = RwLock::new(Vec::new());
+
+// Reads a pack.txt, `key = value` lines
+pub fn parse(text: &str, dir: &Path)
+-> Result<(Pack, Vec<Flag>), String> {
+    let mut pack = Pack {
+        name: String::from("Unnamed pack"),
+        title: String::from("Tohorank"),
+        noun: String::from("character"),
+        list: String::from("characters.txt"),
+        dir: dir.to_path_buf(),
+        stock: false,
+    };
+    let mut flags: Vec<Flag> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected `key = value`", number + 1));
+        };
+        let value = value.trim().to_string();
+        match key.trim() {
+            "name" => { pack.name = value; },
+            "title" => { pack.title = value; },
+            "noun" => { pack.noun = value; },
+            "list" => { pack.list = value; },
+            "flag" => {
+                let fields: Vec<&str> = value.split(';').map(|a| a.trim()).collect();
+                if !(3..=4).contains(&fields.len()) || fields[0].is_empty() || fields[0].contains(' ') || fields[0].ends_with('!') {
+                    return Err(format!("line {}: expected `flag = key; description; include/exclude; aliases`", number + 1));
+                }
+                let include = match fields[2] {
+                    "include" => true,
+                    "exclude" => false,
+                    other => { return Err(format!("line {}: \"{}\" is neither include nor exclude", number + 1, other)); },
+                };
+                if flags.iter().any(|a| a.key == fields[0]) {
+                    return Err(format!("line {}: the flag {} is already there", number + 1, fields[0]));
+                }
+                flags.push(Flag {
+                    key: fields[0].to_string(),
+                    name: fields[1].to_string(),
+                    include,
+                    aliases: fields.get(3).unwrap_or(&"").split(' ').filter(|a| !a.is_empty()).map(|a| a.to_string()).collect(),
+                });
+            },
+            other => { return Err(format!("line {}: unknown setting \"{}\"", number + 1, other)); },
+        }
+    }
+    Ok((pack, flags))
+}
+
+// Read the pack in a folder
+fn open(dir: &Path)
+-> Result<(Pack, Vec<Flag>), String> {
+    let text = fs::read_to_string(dir.join("pack.txt"))
+        .map_err(|e| format!("{}: {}", dir.join("pack.txt").display(), e))?;
+    parse(&text, dir).map_err(|e| format!("{}: {}", dir.join("pack.txt").display(), e))
+}
+
+// The file of a profile that names its pack
+fn link_path(data_path: &Path)
+-> PathBuf {
+    data_path.with_file_name("pack.link")
+}
+
+// Find the pack of a profile, binding it to `bind` first if given
+// the stock pack keeps its files in `base`, shared by every Touhou profile
+pub fn load(data_path: &Path, bind: Option<&Path>, base: &Path)
+-> Result<(), String> {
+    let link = link_path(data_path);
+    let bound = fs::read_to_string(&link).ok().map(|a| PathBuf::from(a.trim()));
+    let pack = match (bind, bound) {
+        (Some(dir), bound) => {
+            let dir = dir.canonicalize().map_err(|e| format!("{}: {}", dir.display(), e))?;
+            let pack = open(&dir)?;
+            if let Some(bound) = bound.filter(|a| *a != dir) {
+                if data_path.exists() {
+                    return Err(format!("this profile is bound to {}, use a new profile for another pack", bound.display()));
+                }
+            }
+            fs::write(&link, dir.display().to_string()).map_err(|e| e.to_string())?;
+            pack
+        },
+        (None, Some(dir)) => open(&dir)?,
+        // a pack.txt in the base folder changes the stock pack
+        (None, None) if base.join("pack.txt").exists() => {
+            let (pack, flags) = open(base)?;
+            (Pack { stock: true, ..pack }, flags)
+        },
+        (None, None) => {
+            let (pack, flags) = parse(STOCK, base).unwrap();
+            (Pack { stock: true, ..pack }, flags)
+        },
+    };
+    let _ = PACK.set(pack.0);
+    *FLAGS.write().unwrap() = pack.1;
+    Ok(())
+}
+
+// The pack in use
+pub fn get()
+-> &'static Pack {
+    PACK.get().expect("the pack is loaded at startup")
+}
+
+impl Pack {
+    pub fn list_path(&self)
+    -> PathBuf {
+        self.dir.join(&self.list)
+    }
+
+    pub fn tags_path(&self)
+    -> PathBuf {
+        self.dir.join("tags.txt")
+    }
+
+    // "characters"
+    pub fn nouns(&self)
+    -> String {
+        format!("{}s", self.noun)
+    }
+
+    // Write pack.txt with the flags in use
+    pub fn write(&self)
+    -> std::io::Result<()> {
+        let mut text = String::from("# This is a Tohorank dataset pack: what is being sorted.\n");
+        text.push_str("# flag = key; description; include or exclude by default; aliases\n");
+        text.push_str(&format!("name = {}\ntitle = {}\nnoun = {}\nlist = {}\n", self.name, self.title, self.noun, self.list));
+        for flag in flags().iter() {
+            let line = format!("flag = {}; {}; {}; {}", flag.key, flag.name, if flag.include { "include" } else { "exclude" }, flag.aliases.join(" "));
+            text.push_str(line.trim_end().trim_end_matches(';'));
+            text.push('\n');
+        }
+        fs::write(self.dir.join("pack.txt"), text)
+    }
+}
+
+// The flags in use
+pub fn flags()
+-> Vec<Flag> {
+    FLAGS.read().unwrap().clone()
+}
+
+// Use these flags from now on, `Pack::write` keeps them
+pub fn set_flags(flags: Vec<Flag>) {
+    *FLAGS.write().unwrap() = flags;
+}
+
+// A flag in a filter: the key, an alias, or the start of the key ("namel" is enough for "nameless")
+pub fn flag(token: &str)
+-> Option<Flag> {
+    let flags = FLAGS.read().unwrap();
+    flags.iter().find(|a| a.key == token || a.aliases.iter().any(|b| b == token))
+        .or_else(|| flags.iter().find(|a| token.len() >= 4 && a.key.starts_with(token)))
+        .cloned()
+}
+
+// A flag in the character list, only the exact key
+pub fn flag_exact(key: &str)
+-> Option<Flag> {
+    FLAGS.read().unwrap().iter().find(|a| a.key == key).cloned()
+}
+
+// A filter word about a flag, like "-pc98" or "pc98!"
+pub fn flag_rule(token: &str)
+-> Option<(Flag, Rule)> {
+    if let Some(key) = token.strip_prefix('-') {
+        flag(key).map(|a| (a, Rule::Out))
+    } else if let Some(key) = token.strip_suffix('!') {
+        flag(key).map(|a| (a, Rule::Only))
+    } else {
+        flag(token).map(|a| (a, Rule::In))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stock_pack() {
+        let (pack, flags) = parse(STOCK, Path::new("/base")).unwrap();
+        assert_eq!(pack.dir, Path::new("/base"));
+        assert_eq!(pack.list_path(), Path::new("/base").join(&pack.list));
+        assert!(flags.iter().any(|a| a.key == "pc98" && !a.include));
+        assert!(flags.iter().any(|a| a.key == "nameless" && a.include));
+    }
+
+    #[test]
+    fn settings_and_flags() {
+        let text = "# a pack\nname = Vocaloid\ntitle = Vocarank\nnoun = singer\n\nflag = cover; Cover versions; exclude; covers cv\nflag = old; Old ones; include\n";
+        let (pack, flags) = parse(text, Path::new("/packs/vocaloid")).unwrap();
+        assert_eq!((pack.name.as_str(), pack.title.as_str(), pack.noun.as_str()), ("Vocaloid", "Vocarank", "singer"));
+        assert_eq!(pack.list, "characters.txt");
+        assert_eq!(pack.nouns(), "singers");
+        assert!(!pack.stock);
+        assert_eq!(flags.len(), 2);
+        assert_eq!((flags[0].key.as_str(), flags[0].name.as_str(), flags[0].include), ("cover", "Cover versions", false));
+        assert_eq!(flags[0].aliases, vec!["covers", "cv"]);
+        assert!(flags[1].include && flags[1].aliases.is_empty());
+    }
+
+    #[test]
+    fn bad_lines() {
+        let error = |text: &str| parse(text, Path::new("/")).err().unwrap();
+        assert_eq!(error("name Vocaloid"), "line 1: expected `key = value`");
+        assert_eq!(error("\ncolor = red"), "line 2: unknown setting \"color\"");
+        assert!(error("flag = a b; A; include").starts_with("line 1: expected `flag = "));
+        assert_eq!(error("flag = a; A; maybe"), "line 1: \"maybe\" is neither include nor exclude");
+        assert_eq!(error("flag = a; A; include\nflag = a; B; exclude"), "line 2: the flag a is already there");
+    }
+}
@@ -0,0 +1,362 @@
+// Tohorank: reference rankings
+// Reads someone else's ranking (numbered list, CSV or a charasort export),
+// matches the names against ours and keeps it in the profile for `compare`.
+
+use crate::{Chara, Reference, data, norm};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use chrono::Local;
+use colored::Colorize;
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+
+// Split a CSV/TSV line, "quoted, fields" keep their delimiter
+fn split_fields(line: &str, delimiter: char)
+-> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            if quoted && chars.peek() == Some(&'"') {
+                fields.last_mut().unwrap().push('"');
+                chars.next();
+            } else {
+                quoted = !quoted;
+            }
+        } else if c == delimiter && !quoted {
+            fields.push(String::new());
+        } else {
+            fields.last_mut().unwrap().push(c);
+        }
+    }
+    fields.into_iter().map(|a| a.trim().to_string()).collect()
+}
+
+// "12. Name", "12) Name", "12: Name" or "12 Name"
+fn split_numbered(line: &str)
+-> Option<(usize, String)> {
+    let digits = line.find(|c: char| !c.is_ascii_digit())?;
+    if digits == 0 {
+        return None;
+    }
+    let rank = line[..digits].parse().ok()?;
+    let rest = &line[digits..];
+    if !rest.starts_with(['.', ')', ':', ' ']) {
+        return None;
+    }
+    let rest = rest[1..].trim();
+    if rest.is_empty() {
+        return None;
+    }
+    Some((rank, rest.to_string()))
+}
+
+// Reads a ranking file into (name as written, rank)
+// lines without a rank are ranked by their position
+pub fn parse(text: &str)
+-> Vec<(String, usize)> {
+    let lines: Vec<&str> = text.lines()
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty())
+        .collect();
+    // tabs are what charasort's result table gives when copied
+    let delimiter = if lines.iter().any(|a| a.contains('\t')) {
+        Some('\t')
+    } else if lines.iter().filter(|a| a.contains(',')).count() * 2 > lines.len() && lines.iter().all(|a| split_numbered(a).is_none()) {
+        Some(',')
+    } else {
+        None
+    };
+
+    let mut ranking: Vec<(String, usize)> = Vec::with_capacity(lines.len());
+    let Some(delimiter) = delimiter else {
+        // a plain list, numbered or not
+        for (n, line) in lines.iter().enumerate() {
+            match split_numbered(line) {
+                Some((rank, name)) => { ranking.push((name, rank)); },
+                None => { ranking.push((line.to_string(), n + 1)); },
+            }
+        }
+        return ranking;
+    };
+
+    // a table, use the header if there's one
+    let mut rows: Vec<Vec<String>> = lines.iter().map(|a| split_fields(a, delimiter)).collect();
+    let mut columns: (Option<usize>, Option<usize>) = (None, None); // name, rank
+    if let Some(header) = rows.first() {
+        if header.iter().all(|a| a.parse::<usize>().is_err()) {
+            columns.0 = header.iter().position(|a| {
+                let a = a.to_lowercase();
+                a.contains("name") || a.contains("chara")
+            });
+            columns.1 = header.iter().position(|a| {
+                let a = a.to_lowercase();
+                a.contains("rank") || a == "#" || a == "no" || a == "place"
+            });
+            if columns.0.is_some() || columns.1.is_some() {
+                rows.remove(0);
+            }
+        }
+    }
+    for (n, row) in rows.iter().enumerate() {
+        let rank = match columns.1 {
+            Some(col) => row.get(col).and_then(|a| a.trim_end_matches('.').parse().ok()),
+            None => row.iter().find_map(|a| a.trim_end_matches('.').parse().ok()),
+        };
+        // without a header every text field is part of the name,
+        // it's the only way "Shiki Eiki, Yamaxanadu" survives an unquoted CSV
+        let name = match columns.0 {
+            Some(col) => row.get(col).cloned().unwrap_or_default(),
+            None => row.iter()
+                .filter(|a| !a.is_empty() && a.trim_end_matches('.').parse::<usize>().is_err())
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(", "),
+        };
+        if !name.is_empty() {
+            ranking.push((name, rank.unwrap_or(n + 1)));
+        }
+    }
+    ranking
+}
+
+// Other ways to write a name: the part in parentheses ("Okuu"),
+// the name without it, and the given name first ("Reimu Hakurei")
+fn aliases(name: &str)
+-> Vec<String> {
+    let mut result = Vec::new();
+    let base = match name.split_once('(') {
+        Some((base, rest)) => {
+            result.push(rest.trim_end_matches(')').trim().to_string());
+            base.trim().to_string()
+        },
+        None => name.to_string(),
+    };
+    let base = base.trim_end_matches(',').to_string();
+    let words: Vec<&str> = base.split(' ').collect();
+    if words.len() == 2 {
+        result.push(format!("{} {}", words[1], words[0]));
+    }
+    // "Aya" alone, words shared by several characters are dropped later
+    if words.len() > 1 {
+        result.extend(words.iter().map(|a| a.to_string()));
+    }
+    result.push(base);
+    result
+}
+
+// The names of a ranking file, matched against ours
+pub struct Resolved {
+    pub ranks: Vec<(String, usize)>,        // name in our data, rank
+    pub guessed: Vec<(String, String)>,     // fuzzy matches: as written, ours
+    pub unmatched: Vec<String>,
+}
+
+// How close a fuzzy guess has to be, against the score of the name matching itself
+// "Kochiya Sane" is 0.97 of a perfect match, "Goku" for Goutokuji Mike only 0.89
+const GUESS_SCORE: f64 = 0.9;
+
+// Match the names against ours: exact, then aliases, then a fuzzy guess
+pub fn resolve(raw: &[(String, usize)], touhous: &[Chara])
+-> Resolved {
+    // aliases shared by several characters are useless, but the plain names
+    // go first so "Reimu Hakurei" is Hakurei Reimu and not her PC-98 self
+    let mut alias_map: HashMap<String, Option<usize>> = HashMap::new();
+    for plain in [true, false] {
+        let mut round: HashMap<String, Option<usize>> = HashMap::new();
+        for (id, th) in touhous.iter().enumerate().filter(|(_, th)| th.name.contains('(') != plain) {
+            for alias in aliases(&th.name) {
+                round.entry(alias.to_lowercase())
+                    .and_modify(|a| if *a != Some(id) { *a = None })
+                    .or_insert(Some(id));
+            }
+        }
+        for (alias, id) in round {
+            alias_map.entry(alias).or_insert(id);
+        }
+    }
+    let matcher = SkimMatcherV2::default();
+    let mut ranks: Vec<(String, usize)> = Vec::with_capacity(raw.len());
+    let mut guessed: Vec<(String, String)> = Vec::new();
+    let mut unmatched: Vec<String> = Vec::new();
+    for (name, rank) in raw.iter() {
+        let lower = name.to_lowercase();
+        let found = touhous.iter().position(|th| th.name.to_lowercase() == lower)
+            .or_else(|| alias_map.get(&lower).cloned().flatten())
+            .or_else(|| {
+                // an alias of several characters is no better as a guess
+                if alias_map.contains_key(&lower) {
+                    return None;
+                }
+                let perfect = matcher.fuzzy_match(name, name)? as f64;
+                let guess = touhous.iter()
+                    .enumerate()
+                    .filter_map(|(id, th)| matcher.fuzzy_match(&th.name, name).map(|score| (score, id)))
+                    .max_by_key(|(score, id)| (*score, Reverse(touhous[*id].name.len())))
+                    .filter(|(score, _)| *score as f64 >= perfect * GUESS_SCORE)
+                    .map(|a| a.1);
+                if let Some(id) = guess {
+                    guessed.push((name.clone(), touhous[id].name.clone()));
+                }
+                guess
+            });
+        match found {
+            Some(id) if !ranks.iter().any(|a| a.0 == touhous[id].name) => {
+                ranks.push((touhous[id].name.clone(), *rank));
+            },
+            Some(id) => { unmatched.push(format!("{} (again {})", name, touhous[id].name)); },
+            None => { unmatched.push(name.clone()); },
+        }
+    }
+    Resolved { ranks, guessed, unmatched }
+}
+
+// Read a file into a new reference
+fn import(path: &Path, name: &str, touhous: &[Chara])
+-> Result<Reference, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let raw = parse(&text);
+    if raw.is_empty() {
+        return Err("there's no ranking in it".to_string());
+    }
+    let Resolved { ranks, guessed, unmatched } = resolve(&raw, touhous);
+    println!("==> {}", format!("IMPORTED \"{}\"", name).bold());
+    println!("    {} of {} names matched.", ranks.len(), raw.len());
+    for (written, ours) in guessed.iter() {
+        println!("    {} \"{}\" is {}?", "Guessed:".yellow(), written, ours);
+    }
+    for written in unmatched.iter() {
+        println!("    {} {}", "Not found:".red(), written);
+    }
+    println!();
+    Ok(Reference {
+        name: name.to_string(),
+        source: path.display().to_string(),
+        added: Local::now().format("%Y-%m-%d %H:%M").to_string(),
+        ranks,
+    })
+}
+
+// The library of references in the profile
+fn show_library(library: &[Reference]) {
+    println!("==> {}", "REFERENCES".bold());
+    if library.is_empty() {
+        println!("    None yet, add one with `compare [file]`.");
+    }
+    for reference in library.iter() {
+        println!("    {:<24} {:>4} characters, from {} on {}",
+            reference.name.bold(),
+            reference.ranks.len(),
+            reference.source,
+            reference.added
+        );
+    }
+    println!("\nⓘ `compare [file] (as [name])` adds a ranking file (numbered list, CSV, charasort export),");
+    println!("  `compare [name]` compares against a saved one, `compare rm [name]` removes it.\n");
+}
+
+// The `compare` command
+pub fn command(line: &str, touhous: &[Chara], data_path: &Path) {
+    let args = line.trim().split_once(' ').map(|a| a.1.trim()).unwrap_or("");
+    let mut library = data::read_references(data_path);
+    if args.is_empty() {
+        show_library(&library);
+        return;
+    }
+    if let Some(name) = args.strip_prefix("rm ") {
+        let before = library.len();
+        library.retain(|a| !a.name.eq_ignore_ascii_case(name.trim()));
+        if library.len() == before {
+            println!("No reference named \"{}\".", name.trim());
+        } else {
+            data::write_references(&library, data_path);
+            println!("Removed \"{}\".", name.trim());
+        }
+        return;
+    }
+
+    // a file to import, or a saved reference
+    let (target, name) = match args.rsplit_once(" as ") {
+        Some((file, name)) => (file.trim(), Some(name.trim())),
+        None => (args, None),
+    };
+    let path = Path::new(target);
+    let reference = if path.is_file() {
+        let name = name.map(|a| a.to_string()).unwrap_or_else(|| {
+            path.file_stem().map(|a| a.to_string_lossy().to_string()).unwrap_or(target.to_string())
+        });
+        match import(path, &name, touhous) {
+            Ok(reference) => {
+                library.retain(|a| !a.name.eq_ignore_ascii_case(&reference.name));
+                library.push(reference.clone());
+                data::write_references(&library, data_path);
+                reference
+            },
+            Err(e) => {
+                println!("Couldn't read \"{}\": {}", target, e);
+                return;
+            },
+        }
+    } else {
+        match library.iter().find(|a| a.name.eq_ignore_ascii_case(target)) {
+            Some(reference) => reference.clone(),
+            None => {
+                println!("No file or saved reference named \"{}\".", target);
+                return;
+            },
+        }
+    };
+
+    println!("Comparing with \"{}\"...", reference.name);
+    let list = norm::build(touhous.iter().collect(), &reference.ranks);
+    // the full orderings, for RBO
+    let mut ours: Vec<&Chara> = touhous.iter().filter(|th| !th.dont_know()).collect();
+    ours.sort_by(|a, b| b.rank.rate.partial_cmp(&a.rank.rate).unwrap());
+    let ours: Vec<String> = ours.into_iter().map(|th| th.name.clone()).collect();
+    let mut theirs = reference.ranks.clone();
+    theirs.sort_by_key(|a| a.1);
+    let theirs: Vec<String> = theirs.into_iter().map(|a| a.0).collect();
+    norm::show(&list, &ours, &theirs, &mut rand::thread_rng());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(list: &[(&str, usize)])
+    -> Vec<(String, usize)> {
+        list.iter().map(|a| (a.0.to_string(), a.1)).collect()
+    }
+
+    #[test]
+    fn numbered_and_plain_lists() {
+        assert_eq!(parse("1. Cirno\n2) Rumia\n\n4: Daiyousei\n5 Koakuma\n"),
+            owned(&[("Cirno", 1), ("Rumia", 2), ("Daiyousei", 4), ("Koakuma", 5)]));
+        assert_eq!(parse("Cirno\nRumia\n"), owned(&[("Cirno", 1), ("Rumia", 2)]));
+    }
+
+    #[test]
+    fn tables() {
+        let csv = "Rank,Name\n1,Cirno\n2,\"Shiki Eiki, Yamaxanadu\"\n";
+        assert_eq!(parse(csv), owned(&[("Cirno", 1), ("Shiki Eiki, Yamaxanadu", 2)]));
+        // no header, the unquoted comma stays in the name
+        assert_eq!(parse("1,Shiki Eiki, Yamaxanadu\n2,Cirno\n"), owned(&[("Shiki Eiki, Yamaxanadu", 1), ("Cirno", 2)]));
+        // charasort's table, copied
+        assert_eq!(parse("Order\tName\n1.\tCirno\n2.\tRumia\n"), owned(&[("Cirno", 1), ("Rumia", 2)]));
+    }
+
+    #[test]
+    fn resolve_names() {
+        let touhous: Vec<Chara> = ["Hakurei Reimu", "Kochiya Sanae", "Yakumo Ran", "Yakumo Yukari", "Goutokuji Mike"].iter()
+            .map(|a| Chara::rated(a, 1500.0, 350.0))
+            .collect();
+        let raw = owned(&[("Reimu Hakurei", 1), ("Ran", 2), ("Kochiya Sane", 3), ("Goku", 4), ("Yakumo", 5)]);
+        let resolved = resolve(&raw, &touhous);
+        assert_eq!(resolved.ranks, owned(&[("Hakurei Reimu", 1), ("Yakumo Ran", 2), ("Kochiya Sanae", 3)]));
+        assert_eq!(resolved.guessed, vec![("Kochiya Sane".to_string(), "Kochiya Sanae".to_string())]);
+        assert_eq!(resolved.unmatched, vec!["Goku".to_string(), "Yakumo".to_string()]);
+    }
+}
@@ -0,0 +1,422 @@
+// Tohorank: standalone HTML report
+// One self-contained page: ranking, groups, head-to-head matrix and a page per character.
+// No scripts or fonts are fetched, so it can be archived or hosted as a static file.
+
+use crate::{INCLUSIVE, DEVIATION_BAR, Chara, Session, Tags, groups, pack, stats};
+use crate::export::{escape, portrait};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// most characters in the head-to-head matrix, by rating
+const MATRIX_SIZE: usize = 30;
+// chart size in pixels
+const CHART_W: f64 = 640.0;
+const CHART_H: f64 = 180.0;
+
+const STYLE: &str = "
+body { background: #1e1f24; color: #e8e8e8; font-family: 'DejaVu Sans', 'Noto Sans', Arial, sans-serif; margin: 0 auto; max-width: 1100px; padding: 0 20px 40px; }
+a { color: #8cb4fa; text-decoration: none; }
+a:hover { text-decoration: underline; }
+h1 { margin-bottom: 0; }
+h2 { border-bottom: 1px solid #44464f; padding-bottom: 4px; margin-top: 40px; }
+nav a { margin-right: 16px; }
+.muted { color: #9a9ca3; }
+table { border-collapse: collapse; margin: 8px 0; }
+th, td { padding: 3px 10px; text-align: left; }
+th { border-bottom: 1px solid #44464f; }
+tbody tr:nth-child(even) { background: rgba(255, 255, 255, 0.03); }
+td.num, th.num { text-align: right; font-variant-numeric: tabular-nums; }
+.sortable th { cursor: pointer; user-select: none; }
+.sortable th.asc::after { content: ' ▲'; }
+.sortable th.desc::after { content: ' ▼'; }
+.gold { color: #f5d45f; } .silver { color: #b4f5d4; } .bronze { color: #f08c5f; }
+.unsure { color: #b6b9bf; }
+.win { color: #8cb4fa; } .loss { color: #f0705f; }
+.me { font-weight: bold; }
+.chara { display: none; }
+.chara:target { display: block; }
+.portrait { float: right; width: 120px; height: 120px; object-fit: cover; border-radius: 6px; }
+.matrix td { text-align: center; min-width: 28px; padding: 2px 4px; font-size: 12px; }
+.matrix th.col { writing-mode: vertical-rl; transform: rotate(180deg); font-weight: normal; font-size: 12px; padding: 4px 2px; }
+.matrix td.ahead { background: rgba(140, 180, 250, 0.35); }
+.matrix td.behind { background: rgba(240, 112, 95, 0.35); }
+.matrix td.even { background: rgba(255, 255, 255, 0.08); }
+.rankings { display: flex; flex-wrap: wrap; gap: 8px 32px; }
+svg text { fill: #9a9ca3; font-size: 11px; }
+";
+
+// click a header to sort by it, numbers compare as numbers
+const SCRIPT: &str = "
+document.querySelectorAll('table.sortable').forEach(function (table) {
+  table.querySelectorAll('th').forEach(function (th, col) {
+    th.addEventListener('click', function () {
+      var body = table.tBodies[0];
+      var asc = !th.classList.contains('asc');
+      table.querySelectorAll('th').forEach(function (a) { a.classList.remove('asc', 'desc'); });
+      th.classList.add(asc ? 'asc' : 'desc');
+      var key = function (row) { var cell = row.cells[col]; return cell.dataset.value !== undefined ? cell.dataset.value : cell.textContent; };
+      var rows = Array.prototype.slice.call(body.rows);
+      rows.sort(function (a, b) {
+        var x = key(a), y = key(b), nx = parseFloat(x), ny = parseFloat(y);
+        var order = (!isNaN(nx) && !isNaN(ny)) ? nx - ny : x.localeCompare(y);
+        return asc ? order : -order;
+      });
+      rows.forEach(function (row) { body.appendChild(row); });
+    });
+  });
+});
+";
+
+// Writes the report of the whole profile to path
+// Portraits are taken from the same folder as export-image's
+pub fn write(touhous: &Vec<Chara>, log: &[Session], path: &Path, portraits: Option<&Path>)
+-> Result<(), String> {
+    let everyone = stats::filter_group(vec![], touhous);
+    let battles: usize = log.iter().map(|a| a.records.len()).sum();
+
+    let mut html = String::new();
+    let _ = write!(html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{} report</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1 id=\"top\">{}</h1>\n\
+         <p class=\"muted\">Generated {} · {} {} ranked · {} battles in {} sessions</p>\n\
+         <nav><a href=\"#ranking\">Ranking</a><a href=\"#groups\">Groups</a><a href=\"#matrix\">Head-to-head</a></nav>\n",
+        escape(&pack::get().title),
+        STYLE,
+        escape(&pack::get().title),
+        chrono::Local::now().format("%Y-%m-%d %H:%M"),
+        everyone.iter().filter(|a| !a.dont_know()).count(),
+        escape(&pack::get().nouns()),
+        battles,
+        log.len()
+    );
+    ranking(&mut html, touhous, &everyone);
+    groups(&mut html, touhous);
+    matrix(&mut html, touhous, log);
+    html.push_str("<section id=\"characters\">\n");
+    for (id, th) in touhous.iter().enumerate() {
+        if !th.dont_know() {
+            page(&mut html, id, th, touhous, &everyone, portraits);
+        }
+    }
+    let _ = write!(html, "</section>\n<script>{}</script>\n</body>\n</html>\n", SCRIPT);
+    fs::write(path, html).map_err(|e| e.to_string())
+}
+
+// Class of the medal colors
+fn medal(rank: usize)
+-> &'static str {
+    match rank {
+        1 => "gold",
+        2 => "silver",
+        3 => "bronze",
+        _ => "",
+    }
+}
+
+// Same names as the `groups` screen
+fn group_title(tag: &Tags)
+-> String {
+    if tag.exname() != "" {
+        format!("TH{} {}", tag.exname().split(' ').next().unwrap_or(""), tag.name())
+    } else {
+        tag.name().to_string()
+    }
+}
+
+// The sortable ranking table
+fn ranking(html: &mut String, touhous: &[Chara], everyone: &Vec<&Chara>) {
+    let mut sorted: Vec<(usize, &Chara)> = touhous.iter()
+        .enumerate()
+        .filter(|(_, th)| !th.dont_know())
+        .collect();
+    sorted.sort_by(|a, b| b.1.rank.rate.partial_cmp(&a.1.rank.rate).unwrap());
+
+    html.push_str("<h2 id=\"ranking\">Ranking</h2>\n<table class=\"sortable\">\n<thead><tr>\
+        <th class=\"num\">#</th><th>Name</th><th class=\"num\">Rating</th><th class=\"num\">±</th>\
+        <th class=\"num\">Wins</th><th class=\"num\">Draws</th><th class=\"num\">Losses</th><th>Works</th>\
+        </tr></thead>\n<tbody>\n");
+    for (id, th) in sorted {
+        let rank = stats::rank_in_group(th, everyone).0;
+        let works: Vec<String> = Tags::iter()
+            .filter(|tag| tag.is_series_tag() && th.has_tag(tag))
+            .map(|tag| tag.key())
+            .collect();
+        let _ = writeln!(html,
+            "<tr class=\"{}\"><td class=\"num\" data-value=\"{}\">{}.</td><td><a href=\"#c{}\">{}</a></td>\
+             <td class=\"num\">{:.0}</td><td class=\"num{}\">{:.0}</td>\
+             <td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"muted\">{}</td></tr>",
+            medal(rank), rank, rank, id, escape(&th.name),
+            th.rank.rate,
+            if th.rank.devi > DEVIATION_BAR { " unsure" } else { "" },
+            th.rank.devi * 1.96,
+            th.hist.wins, th.hist.draw, th.hist.loss,
+            works.join(" ")
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+// Aggregates of works and stages, like `groups`
+fn groups(html: &mut String, touhous: &Vec<Chara>) {
+    let favorites: Vec<bool> = touhous.iter()
+        .map(|th| !th.dont_know() && stats::is_favorite(th, touhous))
+        .collect();
+    let mut all: Vec<stats::GroupStats<Tags>> = Tags::iter()
+        .filter_map(|tag| stats::group_stats(tag, touhous, &favorites))
+        .collect();
+    all.sort_by(|a, b| b.mean.partial_cmp(&a.mean).unwrap());
+
+    html.push_str("<h2 id=\"groups\">Groups</h2>\n");
+    for category in groups::categories() {
+        let list: Vec<&stats::GroupStats<Tags>> = all.iter().filter(|a| a.group.category() == category).collect();
+        if list.is_empty() {
+            continue;
+        }
+        let title: String = groups::category_title(&category).chars()
+            .enumerate()
+            .map(|(n, c)| if n == 0 { c } else { c.to_ascii_lowercase() })
+            .collect();
+        let _ = write!(html, "<h3>{}</h3>\n<table class=\"sortable\">\n<thead><tr>\
+            <th class=\"num\">#</th><th>Group</th><th class=\"num\">Size</th><th class=\"num\">Mean</th>\
+            <th class=\"num\">Median</th><th class=\"num\">Top 10</th><th class=\"num\">★</th><th class=\"num\">±</th>\
+            </tr></thead>\n<tbody>\n", title);
+        for (n, group) in list.iter().enumerate() {
+            let _ = writeln!(html,
+                "<tr class=\"{}\"><td class=\"num\" data-value=\"{}\">{}.</td><td>{}</td><td class=\"num\">{}</td>\
+                 <td class=\"num\">{:.0}</td><td class=\"num\">{:.0}</td><td class=\"num\">{}</td>\
+                 <td class=\"num\">{:.0}%</td><td class=\"num\">{:.0}</td></tr>",
+                medal(n + 1), n + 1, n + 1, escape(&group_title(&group.group)),
+                group.size, group.mean, group.median, group.top,
+                group.favorite * 100.0, group.devi
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+    html.push_str("<p class=\"muted\">★: share of members who are #1 in any of their groups. ±: average 1.96·RD.</p>\n");
+}
+
+// Wins, draws and losses of every pair in the match log, keyed (smaller id, larger id)
+// from the point of view of the smaller id
+fn head_to_head(log: &[Session])
+-> HashMap<(usize, usize), (usize, usize, usize)> {
+    let mut pairs: HashMap<(usize, usize), (usize, usize, usize)> = HashMap::new();
+    for battle in log.iter().flat_map(|a| a.records.iter()) {
+        let flipped = battle.one > battle.two;
+        let entry = pairs.entry((battle.one.min(battle.two), battle.one.max(battle.two))).or_default();
+        if battle.res == 0.5 {
+            entry.1 += 1;
+        } else if battle.res == 1.0 && !flipped || battle.res == 0.0 && flipped {
+            entry.0 += 1;
+        } else if battle.res == 1.0 || battle.res == 0.0 {
+            entry.2 += 1;
+        }
+    }
+    pairs
+}
+
+// The head-to-head matrix of the top characters who've been battled
+fn matrix(html: &mut String, touhous: &[Chara], log: &[Session]) {
+    let pairs = head_to_head(log);
+    let mut shown: Vec<usize> = (0..touhous.len())
+        .filter(|id| !touhous[*id].dont_know())
+        .filter(|id| pairs.keys().any(|(a, b)| a == id || b == id))
+        .collect();
+    shown.sort_by(|a, b| touhous[*b].rank.rate.partial_cmp(&touhous[*a].rank.rate).unwrap());
+    shown.truncate(MATRIX_SIZE);
+
+    html.push_str("<h2 id=\"matrix\">Head-to-head</h2>\n");
+    if shown.is_empty() {
+        html.push_str("<p class=\"muted\">No battles in the match log yet.</p>\n");
+        return;
+    }
+    let _ = writeln!(html,
+        "<p class=\"muted\">Wins-losses of the row against the column, over the whole match log. Top {} by rating.</p>",
+        shown.len()
+    );
+    html.push_str("<table class=\"matrix\">\n<thead><tr><th></th>");
+    for id in shown.iter() {
+        let _ = write!(html, "<th class=\"col\"><a href=\"#c{}\">{}</a></th>", id, escape(&touhous[*id].name));
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for row in shown.iter() {
+        let _ = write!(html, "<tr><th><a href=\"#c{}\">{}</a></th>", row, escape(&touhous[*row].name));
+        for col in shown.iter() {
+            let key = (*row.min(col), *row.max(col));
+            match pairs.get(&key) {
+                Some((w, d, l)) if row != col => {
+                    let (wins, losses) = if row < col { (w, l) } else { (l, w) };
+                    let _ = write!(html, "<td class=\"{}\" title=\"{} vs {}: {} wins, {} draws, {} losses\">{}-{}</td>",
+                        if wins > losses { "ahead" } else if wins < losses { "behind" } else { "even" },
+                        escape(&touhous[*row].name), escape(&touhous[*col].name),
+                        wins, d, losses, wins, losses
+                    );
+                },
+                _ => { html.push_str("<td></td>"); },
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+// A line chart as inline SVG, `invert` puts the lowest value on top (for ranks)
+// band is an optional (upper, lower) pair drawn as a shaded area
+fn chart(values: &[f64], band: Option<(&[f64], &[f64])>, invert: bool, label: &str)
+-> String {
+    let (low, high) = match band {
+        Some((upper, lower)) => (
+            lower.iter().cloned().fold(f64::INFINITY, f64::min),
+            upper.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        ),
+        None => (
+            values.iter().cloned().fold(f64::INFINITY, f64::min),
+            values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        ),
+    };
+    let (low, high) = if high - low < 1.0 { (low - 1.0, high + 1.0) } else { (low, high) };
+    let (left, top, width, height) = (50.0, 10.0, CHART_W - 60.0, CHART_H - 30.0);
+    let last = (values.len() - 1).max(1) as f64;
+    let x = |n: usize| left + n as f64 / last * width;
+    let y = |v: f64| if invert {
+        top + (v - low) / (high - low) * height
+    } else {
+        top + (high - v) / (high - low) * height
+    };
+    let points = |list: &[f64]| -> Vec<String> {
+        list.iter().enumerate().map(|(n, v)| format!("{:.1},{:.1}", x(n), y(*v))).collect()
+    };
+
+    let mut svg = format!("<svg width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">", CHART_W, CHART_H, CHART_W, CHART_H);
+    let _ = write!(svg, "<line x1=\"{l}\" y1=\"{t}\" x2=\"{l}\" y2=\"{b}\" stroke=\"#44464f\"/>\
+        <line x1=\"{l}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#44464f\"/>",
+        l = left, t = top, b = top + height, r = left + width);
+    let _ = write!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{:.0}</text><text x=\"{}\" y=\"{}\" text-anchor=\"end\">{:.0}</text>",
+        left - 4.0, top + 8.0, if invert { low } else { high },
+        left - 4.0, top + height, if invert { high } else { low });
+    let _ = write!(svg, "<text x=\"{}\" y=\"{}\">{}</text>", left, CHART_H - 4.0, escape(label));
+    if let Some((upper, lower)) = band {
+        let mut area = points(upper);
+        area.extend(points(lower).into_iter().rev());
+        let _ = write!(svg, "<polygon points=\"{}\" fill=\"#8cb4fa\" fill-opacity=\"0.15\"/>", area.join(" "));
+    }
+    let _ = write!(svg, "<polyline points=\"{}\" fill=\"none\" stroke=\"#8cb4fa\" stroke-width=\"2\"/>", points(values).join(" "));
+    // mark the best point
+    let best = values.iter()
+        .enumerate()
+        .max_by(|a, b| if invert { b.1.partial_cmp(a.1) } else { a.1.partial_cmp(b.1) }.unwrap())
+        .unwrap();
+    let _ = write!(svg, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"#f5d45f\"/>", x(best.0), y(*best.1));
+    svg.push_str("</svg>");
+    svg
+}
+
+// A group ranking around the character, like stats::print_rank_in_group()
+fn rank_slice(html: &mut String, chara: &Chara, tag: Option<Tags>, touhous: &Vec<Chara>) {
+    let group = match &tag {
+        Some(tag) => stats::filter_group(vec![(tag.clone(), INCLUSIVE)], touhous),
+        None => stats::filter_group(vec![], touhous),
+    };
+    let rank = stats::rank_in_group(chara, &group);
+    let _ = write!(html, "<table>\n<thead><tr><th colspan=\"2\">{}</th><th class=\"num\">#{}/{}</th></tr></thead>\n<tbody>\n",
+        match &tag {
+            Some(tag) => escape(&group_title(tag)),
+            None => "Overall".to_string(),
+        },
+        rank.0, rank.1
+    );
+    for th in stats::rank_slice_by_chara(chara, &group) {
+        let rank = stats::rank_in_group(th, &group).0;
+        let _ = writeln!(html, "<tr class=\"{}{}\"><td class=\"num\">{}.</td><td>{}</td><td class=\"num\">{:.0} ± {:.0}</td></tr>",
+            medal(rank),
+            if th.name == chara.name { " me" } else { "" },
+            rank, escape(&th.name), th.rank.rate, th.rank.devi * 1.96
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+// The page of one character, like `stat!`
+fn page(html: &mut String, id: usize, chara: &Chara, touhous: &Vec<Chara>, everyone: &Vec<&Chara>, portraits: Option<&Path>) {
+    let rank = stats::rank_in_group(chara, everyone);
+    let _ = writeln!(html, "<article class=\"chara\" id=\"c{}\">\n<h2>{} <span class=\"muted\">Rank #{}/{}</span></h2>",
+        id, escape(&chara.name), rank.0, rank.1);
+    if let Some(uri) = portrait(chara, portraits) {
+        let _ = writeln!(html, "<img class=\"portrait\" src=\"{}\" alt=\"\">", uri);
+    }
+
+    // Rating
+    let _ = writeln!(html, "<h3>Rating</h3>\n<p><b>{:.2}</b> ± {:.0} <span class=\"muted\">(volatility: {:.6})</span></p>",
+        chara.rank.rate, chara.rank.devi * 1.96, chara.rank.vola);
+    if chara.rank.devi > DEVIATION_BAR {
+        html.push_str("<p class=\"muted\">ⓘ The uncertainty is high, do more battles!</p>\n");
+    }
+    if let Some((prk, prk_time)) = &chara.hist.peak_rank {
+        let _ = writeln!(html, "<p>Highest rank: #{} on {}</p>", prk, escape(prk_time));
+    }
+    if let Some((prt, prt_time)) = &chara.hist.peak_rate {
+        let _ = writeln!(html, "<p>Highest rating: {:.0} on {}</p>", prt, escape(prt_time));
+    }
+
+    // History
+    html.push_str("<h3>History</h3>\n");
+    let timeline = &chara.hist.timeline;
+    if timeline.len() < 2 {
+        let _ = writeln!(html, "<p class=\"muted\">ⓘ Charts appear after {} has been in 2 sessions.</p>", escape(&chara.name));
+    } else {
+        let rates: Vec<f64> = timeline.iter().map(|a| a.rate).collect();
+        let upper: Vec<f64> = timeline.iter().map(|a| a.rate + a.devi * 1.96).collect();
+        let lower: Vec<f64> = timeline.iter().map(|a| a.rate - a.devi * 1.96).collect();
+        let ranks: Vec<f64> = timeline.iter().map(|a| a.rank as f64).collect();
+        let _ = writeln!(html, "<div>{}</div>\n<div>{}</div>",
+            chart(&rates, Some((&upper, &lower)), false, &format!("Rating ± 1.96·RD over {} sessions, {} to {}",
+                timeline.len(), timeline[0].time, timeline.last().unwrap().time)),
+            chart(&ranks, None, true, "Rank (lower is better)")
+        );
+    }
+
+    // Rankings, overall then every group
+    html.push_str("<h3>Rankings</h3>\n<div class=\"rankings\">\n");
+    rank_slice(html, chara, None, touhous);
+    for tag in Tags::iter().filter(|tag| chara.has_tag(tag)) {
+        rank_slice(html, chara, Some(tag), touhous);
+    }
+    html.push_str("</div>\n");
+
+    // Statistics
+    let total = chara.hist.wins + chara.hist.draw + chara.hist.loss;
+    let _ = writeln!(html, "<h3>Statistics</h3>\n<p>Wins: {} ({}%) · Draws: {} · Losses: {}</p>",
+        chara.hist.wins,
+        (100 * chara.hist.wins).checked_div(total).unwrap_or(0),
+        chara.hist.draw,
+        chara.hist.loss
+    );
+
+    // Recent battles
+    if !chara.recent.is_empty() {
+        html.push_str("<h3>Recent battles</h3>\n<table>\n<tbody>\n");
+        for battle in chara.recent.iter() {
+            let side = if touhous[battle.one].name == chara.name { 1 } else { 2 };
+            let other = if side == 1 { &touhous[battle.two] } else { &touhous[battle.one] };
+            let (class, result) = if battle.res == 0.5 {
+                ("", "Drew")
+            } else if battle.res == 2.0 {
+                ("loss", "Drew (lost)")
+            } else if (battle.res == 1.0) == (side == 1) {
+                ("win", "Won")
+            } else {
+                ("loss", "Lost")
+            };
+            let _ = writeln!(html, "<tr><td class=\"{}\">{}</td><td>against <a href=\"#c{}\">{}</a></td><td class=\"num\">{:.0}</td></tr>",
+                class, result,
+                if side == 1 { battle.two } else { battle.one },
+                escape(&other.name), other.rank.rate
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+    html.push_str("<p><a href=\"#ranking\">Back to the ranking</a></p>\n</article>\n");
+}
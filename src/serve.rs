@@ -0,0 +1,426 @@
+// Tohorank: the browser version
+// `tohorank serve --port N` answers on localhost with a small web page and a JSON API.
+// Sessions work like in the lobby: matchmake, pick, undo, end, and the ratings
+// are only computed and saved when the session ends.
+
+use crate::{Chara, Match, chara, export, meta, pack, sort, stats};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use rand::rngs::ThreadRng;
+
+// the web page, everything else is the API
+const PAGE: &str = include_str!("serve.html");
+
+// A session in progress
+struct Session {
+    indices: Vec<usize>,        // global IDs of the participants, in order
+    picks: HashSet<usize>,      // unranked participants who had their turn, see matchmake()
+    pair: Vec<usize>,           // the battle on screen, positions among the participants
+    records: Vec<Match>,
+    started: Instant,
+}
+
+// A request, only what the API needs
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    host: String,
+    origin: Option<String>,
+    tohorank: bool,     // the X-Tohorank header, a page on another site can't send it without asking first
+}
+
+impl Request {
+    fn param(&self, key: &str)
+    -> Option<&str> {
+        self.query.iter().find(|a| a.0 == key).map(|a| a.1.as_str())
+    }
+}
+
+// Answer on localhost until stopped
+pub fn run(port: u16, touhous: &mut Vec<Chara>, data_path: &Path) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Couldn't listen on port {}: {}", port, e);
+            return;
+        },
+    };
+    println!("=========~ {}: serving at http://localhost:{}/ ~=========", pack::get().title, port);
+    println!("ⓘ Ctrl+C to stop, a session that isn't ended by then is lost.");
+    let mut rng = rand::thread_rng();
+    let mut session: Option<Session> = None;
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        // one connection at a time, one that never says anything can't hold up the rest
+        if stream.set_read_timeout(Some(Duration::from_secs(5))).is_err()
+            || stream.set_write_timeout(Some(Duration::from_secs(5))).is_err() {
+            continue;
+        }
+        let Some(request) = read_request(&mut stream) else {
+            continue;
+        };
+        let (status, kind, body) = handle(&request, port, touhous, &mut session, &mut rng, data_path);
+        let head = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            status, kind, body.len());
+        let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body.as_bytes()));
+    }
+}
+
+// Read the request line and the headers, and skip the body
+fn read_request(stream: &mut TcpStream)
+-> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut words = line.split_whitespace();
+    let method = words.next()?.to_string();
+    let target = words.next()?;
+    let mut length = 0;
+    let mut host = String::new();
+    let mut origin = None;
+    let mut tohorank = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("host") {
+                host = value.to_ascii_lowercase();
+            } else if name.eq_ignore_ascii_case("origin") {
+                origin = Some(value.to_ascii_lowercase());
+            } else if name.eq_ignore_ascii_case("x-tohorank") {
+                tohorank = true;
+            }
+        }
+    }
+    let mut body = vec![0; length.min(1 << 16)];
+    reader.read_exact(&mut body).ok()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Some(Request {
+        method,
+        path: path.to_string(),
+        query: query.split('&')
+            .filter(|a| !a.is_empty())
+            .map(|a| {
+                let (key, value) = a.split_once('=').unwrap_or((a, ""));
+                (decode(key), decode(value))
+            })
+            .collect(),
+        host,
+        origin,
+        tohorank,
+    })
+}
+
+// Undo the %XX and + of a query string
+fn decode(text: &str)
+-> String {
+    let bytes = text.as_bytes();
+    let mut result: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut n = 0;
+    while n < bytes.len() {
+        match bytes[n] {
+            b'+' => { result.push(b' '); },
+            b'%' if n + 2 < bytes.len() && bytes[n + 1].is_ascii_hexdigit() && bytes[n + 2].is_ascii_hexdigit() => {
+                result.push(u8::from_str_radix(&text[n + 1..n + 3], 16).unwrap());
+                n += 2;
+            },
+            byte => { result.push(byte); },
+        }
+        n += 1;
+    }
+    String::from_utf8_lossy(&result).to_string()
+}
+
+// A JSON string
+fn quote(text: &str)
+-> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => { result.push_str("\\\""); },
+            '\\' => { result.push_str("\\\\"); },
+            c if (c as u32) < 0x20 => { result.push_str(&format!("\\u{:04x}", c as u32)); },
+            c => { result.push(c); },
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn error(status: &'static str, text: &str)
+-> (&'static str, &'static str, String) {
+    (status, "application/json", format!("{{\"error\":{}}}", quote(text)))
+}
+
+// Everyone in a session, in the order of the indices
+fn pool<'a>(touhous: &'a mut [Chara], indices: &[usize])
+-> Vec<&'a mut Chara> {
+    touhous.iter_mut()
+        .enumerate()
+        .filter(|(id, _)| indices.contains(id))
+        .map(|(_, th)| th)
+        .collect()
+}
+
+// The battle on screen, or that there's none
+fn battle_json(session: &Option<Session>, touhous: &[Chara], data_path: &Path)
+-> String {
+    let Some(session) = session else {
+        return String::from("{\"active\":false}");
+    };
+    let side = |position: usize| {
+        let id = session.indices[position];
+        let th = &touhous[id];
+        let portrait = export::portrait(th, export::portraits_path(data_path).as_deref());
+        format!("{{\"id\":{},\"name\":{},\"brief\":{},\"portrait\":{}}}",
+            id, quote(&th.name), quote(&meta::brief(th)), portrait.map(|a| quote(&a)).unwrap_or("null".to_string()))
+    };
+    format!("{{\"active\":true,\"number\":{},\"size\":{},\"one\":{},\"two\":{}}}",
+        session.records.len() + 1, session.indices.len(), side(session.pair[0]), side(session.pair[1]))
+}
+
+// The ranking of a filter, like `list`
+fn list_json(touhous: &mut Vec<Chara>, filter: &str)
+-> String {
+    let (tags_filter, name_filter) = {
+        let (_, tags, name) = sort::split_args(&format!("list {}", filter));
+        (tags, name.trim().to_lowercase())
+    };
+    let (invited, indices) = sort::bouncer(tags_filter, touhous);
+    let invited: Vec<(usize, &Chara)> = indices.into_iter()
+        .zip(invited.into_iter().map(|a| &*a))
+        .filter(|(_, th)| !th.dont_know())
+        .collect();
+    let pool: Vec<&Chara> = invited.iter().map(|a| a.1).collect();
+    let mut rows: Vec<(usize, usize, &Chara)> = invited.iter()
+        .filter(|(_, th)| th.name.to_lowercase().contains(&name_filter))
+        .map(|(id, th)| (stats::rank_in_group(th, &pool).0, *id, *th))
+        .collect();
+    rows.sort_by_key(|a| a.0);
+    let rows: Vec<String> = rows.iter()
+        .map(|(rank, id, th)| format!("{{\"rank\":{},\"id\":{},\"name\":{},\"rate\":{:.1},\"devi\":{:.1}}}",
+            rank, id, quote(&th.name), th.rank.rate, th.rank.devi * 1.96))
+        .collect();
+    format!("{{\"list\":[{}]}}", rows.join(","))
+}
+
+// Everything about a character, like `stat`
+fn stat_json(id: usize, touhous: &Vec<Chara>)
+-> String {
+    let th = &touhous[id];
+    let everyone = stats::filter_group(vec![], touhous);
+    let (rank, size) = stats::rank_in_group(th, &everyone);
+    let tags: Vec<String> = th.tags().iter().map(|a| quote(&a.name())).collect();
+    let facts: Vec<String> = meta::FIELDS.iter()
+        .filter_map(|(key, title)| th.meta.get(*key).map(|a| format!("[{},{}]", quote(title), quote(a))))
+        .collect();
+    let recent: Vec<String> = th.recent.iter()
+        .map(|battle| {
+            let (other, mine) = if battle.one == id { (battle.two, battle.res) } else { (battle.one, 1.0 - battle.res) };
+            let result = if battle.res == 0.5 {
+                "Drew"
+            } else if battle.res == 2.0 {
+                "Drew (lost)"
+            } else if mine == 1.0 {
+                "Won"
+            } else {
+                "Lost"
+            };
+            format!("{{\"result\":{},\"against\":{}}}", quote(result), quote(&touhous[other].name))
+        })
+        .collect();
+    format!("{{\"id\":{},\"name\":{},\"rank\":{},\"of\":{},\"rate\":{:.1},\"devi\":{:.1},\"vola\":{:.6},\
+        \"wins\":{},\"loss\":{},\"draw\":{},\"hidden\":{},\"tags\":[{}],\"meta\":[{}],\"recent\":[{}]}}",
+        id, quote(&th.name), rank, size, th.rank.rate, th.rank.devi * 1.96, th.rank.vola,
+        th.hist.wins, th.hist.loss, th.hist.draw, th.dont_know(), tags.join(","), facts.join(","), recent.join(","))
+}
+
+// Why a request is turned away, if it is: only this server's page (or a script) gets answers,
+// not another site open in the browser, or one that points its name at 127.0.0.1
+fn refused(request: &Request, port: u16, changes: bool)
+-> Option<&'static str> {
+    if request.host != format!("localhost:{}", port) && request.host != format!("127.0.0.1:{}", port) {
+        return Some("only answers to localhost");
+    }
+    if changes && request.origin.as_ref().is_some_and(|a| *a != format!("http://{}", request.host)) {
+        return Some("only answers to its own page");
+    }
+    if changes && !request.tohorank {
+        return Some("send the header X-Tohorank: 1");
+    }
+    None
+}
+
+// Answer a request: the status, the content type and the body
+fn handle(request: &Request, port: u16, touhous: &mut Vec<Chara>, session: &mut Option<Session>, rng: &mut ThreadRng, data_path: &Path)
+-> (&'static str, &'static str, String) {
+    let json = |body: String| ("200 OK", "application/json", body);
+    // anything that changes something has to be a POST
+    let changes = matches!(request.path.as_str(), "/api/start" | "/api/pick" | "/api/skip" | "/api/undo" | "/api/forget" | "/api/end");
+    if let Some(why) = refused(request, port, changes) {
+        return error("403 Forbidden", why);
+    }
+    if changes && request.method != "POST" {
+        return error("405 Method Not Allowed", "use POST");
+    }
+    match request.path.as_str() {
+        "/" => ("200 OK", "text/html; charset=utf-8", PAGE.replace("{title}", &export::escape(&pack::get().title))),
+        "/api/info" => json(format!("{{\"title\":{},\"noun\":{},\"nouns\":{},\"size\":{}}}",
+            quote(&pack::get().title), quote(&pack::get().noun), quote(&pack::get().nouns()),
+            touhous.iter().filter(|th| !th.dont_know()).count())),
+        "/api/list" => json(list_json(touhous, request.param("filter").unwrap_or(""))),
+        "/api/stat" => {
+            let found = match request.param("id").and_then(|a| a.parse::<usize>().ok()) {
+                Some(id) if id < touhous.len() => Some(id),
+                Some(_) => None,
+                None => chara::find(touhous, request.param("name").unwrap_or("").to_string())
+                    .and_then(|th| touhous.iter().position(|a| a.name == th.name)),
+            };
+            match found {
+                Some(id) => json(stat_json(id, touhous)),
+                None => error("404 Not Found", "no such character"),
+            }
+        },
+        "/api/battle" => json(battle_json(session, touhous, data_path)),
+        "/api/start" => {
+            if session.is_some() {
+                return error("409 Conflict", "a session is already going, end it first");
+            }
+            let (_, tags_filter, _) = sort::split_args(&format!("start {}", request.param("filter").unwrap_or("")));
+            let (participants, indices) = sort::bouncer(tags_filter, touhous);
+            if participants.len() < 2 {
+                return error("400 Bad Request", "cannot start with fewer than 2 participants");
+            }
+            let mut picks: HashSet<usize> = HashSet::with_capacity(participants.len());
+            let pair = sort::matchmake(rng, &participants, &mut picks);
+            println!("Session started in the browser with {} {}.", indices.len(), pack::get().nouns());
+            *session = Some(Session { indices, picks, pair, records: Vec::new(), started: Instant::now() });
+            json(battle_json(session, touhous, data_path))
+        },
+        _ if !changes => error("404 Not Found", "no such page"),
+        path => {
+            let Some(current) = session.as_mut() else {
+                return error("409 Conflict", "no session going, start one first");
+            };
+            let (one, two) = (current.indices[current.pair[0]], current.indices[current.pair[1]]);
+            match path {
+                // 1 or 2 for the winner, draw, or dislike for both
+                "/api/pick" => {
+                    let res = match request.param("pick").unwrap_or("") {
+                        "1" => 1.0,
+                        "2" => 0.0,
+                        "draw" => 0.5,
+                        "dislike" => 2.0,
+                        _ => { return error("400 Bad Request", "pick is 1, 2, draw or dislike"); },
+                    };
+                    current.records.push(Match { one, two, res });
+                    current.pair = sort::matchmake(rng, &pool(touhous, &current.indices), &mut current.picks);
+                },
+                "/api/skip" => {
+                    current.pair = sort::matchmake(rng, &pool(touhous, &current.indices), &mut current.picks);
+                },
+                // back one battle, and replay it if both are still here
+                "/api/undo" => {
+                    let Some(last) = current.records.pop() else {
+                        return error("400 Bad Request", "this is the first battle");
+                    };
+                    let one = current.indices.iter().position(|a| *a == last.one);
+                    let two = current.indices.iter().position(|a| *a == last.two);
+                    current.pair = match (one, two) {
+                        (Some(one), Some(two)) => vec![one, two],
+                        _ => sort::matchmake(rng, &pool(touhous, &current.indices), &mut current.picks),
+                    };
+                },
+                // "don't know" one side, they're hidden and leave the session
+                "/api/forget" => {
+                    let id = match request.param("side") {
+                        Some("1") => one,
+                        Some("2") => two,
+                        _ => { return error("400 Bad Request", "side is 1 or 2"); },
+                    };
+                    touhous[id].toggle_dont_know();
+                    let mut participants = pool(touhous, &current.indices);
+                    sort::kick(&mut participants, &mut current.indices, &mut current.picks, id);
+                    if participants.len() >= 2 {
+                        current.pair = sort::matchmake(rng, &participants, &mut current.picks);
+                    } else {
+                        let mut ended = session.take().unwrap();
+                        sort::tally(touhous, &mut ended.records, ended.started, data_path);
+                    }
+                },
+                "/api/end" => {
+                    let mut ended = session.take().unwrap();
+                    let battles = ended.records.len();
+                    sort::tally(touhous, &mut ended.records, ended.started, data_path);
+                    return json(format!("{{\"active\":false,\"ended\":{}}}", battles));
+                },
+                _ => unreachable!(),
+            }
+            json(battle_json(session, touhous, data_path))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_queries() {
+        assert_eq!(decode("eosd+-pc98"), "eosd -pc98");
+        assert_eq!(decode("Remilia%20Scarlet"), "Remilia Scarlet");
+        assert_eq!(decode("a%2Bb%2fc"), "a+b/c");
+        assert_eq!(decode("Ren%C3%A9"), "René");
+    }
+
+    #[test]
+    fn keeps_broken_escapes() {
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz%4"), "%zz%4");
+        assert_eq!(decode("%FF"), "\u{fffd}");
+    }
+
+    fn request(host: &str, origin: Option<&str>, tohorank: bool)
+    -> Request {
+        Request {
+            method: "POST".to_string(),
+            path: "/api/pick".to_string(),
+            query: Vec::new(),
+            host: host.to_string(),
+            origin: origin.map(|a| a.to_string()),
+            tohorank,
+        }
+    }
+
+    #[test]
+    fn refuses_other_sites() {
+        assert_eq!(refused(&request("localhost:8080", None, true), 8080, true), None);
+        assert_eq!(refused(&request("127.0.0.1:8080", Some("http://127.0.0.1:8080"), true), 8080, true), None);
+        // looking is fine without the header, changing isn't
+        assert_eq!(refused(&request("localhost:8080", None, false), 8080, false), None);
+        assert!(refused(&request("localhost:8080", None, false), 8080, true).is_some());
+        assert!(refused(&request("localhost:8080", Some("https://example.com"), true), 8080, true).is_some());
+        assert!(refused(&request("localhost:8080", Some("http://127.0.0.1:8080"), true), 8080, true).is_some());
+        // a rebound name, or the wrong port
+        assert!(refused(&request("evil.example:8080", None, true), 8080, false).is_some());
+        assert!(refused(&request("localhost:8081", None, true), 8080, false).is_some());
+        assert!(refused(&request("", None, true), 8080, false).is_some());
+    }
+
+    #[test]
+    fn quotes_json() {
+        assert_eq!(quote("Cirno"), "\"Cirno\"");
+        assert_eq!(quote("a \"b\" \\ c\n"), "\"a \\\"b\\\" \\\\ c\\u000a\"");
+    }
+}
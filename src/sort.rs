@@ -1,14 +1,27 @@
 // Tohorank: actual ranking and sorting
 
-use crate::{Match, Chara, FightCond, groups::Tags, stats};
+use crate::{Match, Chara, Glicko, FightCond, groups::Tags, data, glicko, meta, pack, stats, summary};
+use crate::pack::Rule;
 use std::io::{self, Write};
 use std::str::FromStr;
-use std::collections::HashSet;
+use std::path::Path;
+use std::time::Instant;
+use std::collections::{HashMap, HashSet};
 use colored::Colorize;
 use rand::distributions::{WeightedIndex, Distribution};
 use rand::rngs::ThreadRng;
 use rand::seq::IteratorRandom;
 
+// Ends a session: rates everyone, saves, and shows the summary
+pub fn tally(touhous: &mut Vec<Chara>, records: &mut Vec<Match>, started: Instant, data_path: &Path) {
+    let before = touhous.clone();
+    glicko::calc(touhous, records);
+    data::write_data(touhous, &data_path.to_path_buf());
+    summary::session(&before, touhous, records, started.elapsed(), data_path);
+    records.clear();
+    println!("Data saved! Returning to lobby...");
+}
+
 // Performs one battle, creates a Match record and pushes it to the vec
 // Returns a FightCond so the loop in main() knows what to do next
 pub fn fight(records: &mut Vec<Match>, fire: &mut Chara, ice: &mut Chara, fire_id: usize, ice_id: usize)
@@ -18,6 +31,13 @@ pub fn fight(records: &mut Vec<Match>, fire: &mut Chara, ice: &mut Chara, fire_i
         println!("-----------------------------");
         // println!("Battle #{}: {} ({:.0}) vs {} ({:.0})", records.len() + 1, fire.name.bold(), fire.rank.rate, ice.name.bold(), ice.rank.rate);
         println!("Battle #{}: {} vs {}", records.len() + 1, fire.name.bold(), ice.name.bold());
+        // what the list says about them, if anything
+        for (n, side) in [&*fire, &*ice].iter().enumerate() {
+            let brief = meta::brief(side);
+            if !brief.is_empty() {
+                println!("    {}: {} {}", n + 1, side.name, format!("({})", brief).truecolor(182, 185, 191));
+            }
+        }
         print!("Pick [ 'h' for help ] >> ");
         let _ = io::stdout().flush();
         choice.clear();
@@ -30,7 +50,43 @@ pub fn fight(records: &mut Vec<Match>, fire: &mut Chara, ice: &mut Chara, fire_i
         };
 
         choice = choice.trim().to_string();
-        if choice.ends_with('1') {
+        if choice.starts_with('k') {
+            // I don't know them (k1 or k2)
+            let (who, who_id) = if choice.ends_with('1') {
+                (&mut *fire, fire_id)
+            } else if choice.ends_with('2') {
+                (&mut *ice, ice_id)
+            } else {
+                println!("Use k1 or k2 to pick the side.");
+                continue;
+            };
+            who.toggle_dont_know();
+            if who.dont_know() {
+                println!("{} will be hidden and leaves this session.", who.name.bold());
+                return FightCond::Forget(who_id);
+            }
+            println!("{} will no longer be hidden.", who.name.bold());
+            continue;
+        } else if choice == "live" {
+            // Toggle the live view
+            return FightCond::Live;
+        } else if choice.starts_with("rev") {
+            // Look back at the picks so far
+            return FightCond::Review;
+        } else if choice.starts_with('l') && choice[1..].trim().parse::<usize>().is_ok() {
+            // Undo several steps (l3 or l 3)
+            let steps: usize = choice[1..].trim().parse().unwrap();
+            if records.is_empty() {
+                println!("This is the first battle!");
+                continue;
+            }
+            if steps == 0 {
+                continue;
+            }
+            let steps = steps.min(records.len());
+            println!("Going back {} {}...", steps, if steps == 1 { "step" } else { "steps" });
+            return FightCond::Undo(steps);
+        } else if choice.ends_with('1') {
             // I like left
             println!("Chose - {}!", fire.name.blue());
         } else if choice.ends_with('2') {
@@ -46,18 +102,26 @@ pub fn fight(records: &mut Vec<Match>, fire: &mut Chara, ice: &mut Chara, fire_i
             println!("Disliked both!");
         } else if choice.ends_with('l') {
             // Undo
-            if records.len() == 0 {
+            if records.is_empty() {
                 println!("This is the first battle!");
                 continue;
             }
             println!("Going back...");
-            return FightCond::Undo;
+            return FightCond::Undo(1);
+        } else if choice == "s" {
+            // Skip, nothing is recorded
+            println!("Skipped.");
+            return FightCond::Skip;
         } else if choice.ends_with('h') {
             // Help
             println!("1/2 to choose left/right");
             println!("<Enter> for draws");
             println!("d if you DISLIKE BOTH of them");
-            println!("l to undo");
+            println!("s to skip this pair");
+            println!("k1/k2 if you don't know left/right (hides them)");
+            println!("l to undo, l<n> to undo n steps");
+            println!("\"review\" to see, flip or delete the picks so far");
+            println!("\"live\" to toggle the provisional ratings view");
             println!("\"end\" to end this session");
             continue;
         } else {
@@ -70,6 +134,112 @@ pub fn fight(records: &mut Vec<Match>, fire: &mut Chara, ice: &mut Chara, fire_i
     }
 }
 
+// Lists every pick of this session, lets the user flip or delete any of them
+// roster maps the global IDs to names
+pub fn review(records: &mut Vec<Match>, roster: &HashMap<usize, String>) {
+    let mut command: String = Default::default();
+    loop {
+        println!("----- Review: {} {} -----",
+            records.len(),
+            if records.len() == 1 { "pick" } else { "picks" }
+        );
+        for (n, battle) in records.iter().enumerate() {
+            let (one, two) = (&roster[&battle.one], &roster[&battle.two]);
+            println!("{:>4}. {}",
+                n + 1,
+                if battle.res == 1.0 {
+                    format!("{} > {}", one.blue(), two)
+                } else if battle.res == 0.0 {
+                    format!("{} < {}", one, two.blue())
+                } else if battle.res == 2.0 {
+                    format!("{} & {} (disliked both)", one.red(), two.red())
+                } else {
+                    format!("{} = {}", one, two)
+                }
+            );
+        }
+        print!("Review [ 'flip <n>', 'del <n>', <Enter> to go back ] >> ");
+        let _ = io::stdout().flush();
+        command.clear();
+        let _ = io::stdin().read_line(&mut command);
+
+        let (action, number) = match command.trim().split_once(' ') {
+            Some((a, n)) => (a, n.trim().parse::<usize>().ok()),
+            None => (command.trim(), None),
+        };
+        if action.is_empty() {
+            return;
+        }
+        let Some(id) = number.filter(|n| *n >= 1 && *n <= records.len()).map(|n| n - 1) else {
+            println!("Pick a number between 1 and {}.", records.len());
+            continue;
+        };
+        if action.starts_with('f') {
+            let battle = &mut records[id];
+            if battle.res != 1.0 && battle.res != 0.0 {
+                println!("Draws and dislikes have no winner to flip.");
+                continue;
+            }
+            battle.res = 1.0 - battle.res;
+            println!("Flipped #{}.", id + 1);
+        } else if action.starts_with("del") {
+            records.remove(id);
+            println!("Deleted #{}.", id + 1);
+        } else {
+            println!("?");
+        }
+    }
+}
+
+// Shows the provisional top 10 of the session pool and how the last pick moved things
+// Nothing here is saved, the real tally happens at the end of the session
+pub fn show_live(pool: &[&mut Chara], indices: &[usize], records: &[Match]) {
+    let now = glicko::provisional(pool, indices, records);
+    println!("{}", "----- Live (provisional) -----".truecolor(182, 185, 191));
+    let mut standings: Vec<(&Chara, &Glicko)> = indices.iter()
+        .zip(pool.iter())
+        .filter(|(_, th)| !th.dont_know())
+        .map(|(id, th)| (&**th, &now[id]))
+        .collect();
+    standings.sort_by(|a, b| b.1.rate.partial_cmp(&a.1.rate).unwrap());
+    for (n, (th, rank)) in standings.iter().take(10).enumerate() {
+        let diff = rank.rate - th.rank.rate;
+        println!("{:<4} {:<26}{:>5.0} {}",
+            format!("{}.", n + 1),
+            th.name,
+            rank.rate,
+            if diff > 0.0 {
+                format!("(+{:.0})", diff).blue()
+            } else if diff < 0.0 {
+                format!("({:.0})", diff).red()
+            } else {
+                "".normal()
+            }
+        );
+    }
+    // the last pick, compared to the session without it
+    if let Some(last) = records.last() {
+        let before = glicko::provisional(pool, indices, &records[..records.len() - 1]);
+        println!("Last pick:");
+        for id in [last.one, last.two] {
+            let Some(pos) = indices.iter().position(|a| *a == id) else {
+                continue;
+            };
+            let diff = now[&id].rate - before[&id].rate;
+            println!("    {:<26}{:>5.0} -> {:<5.0} {}",
+                pool[pos].name,
+                before[&id].rate,
+                now[&id].rate,
+                if diff >= 0.0 {
+                    format!("+{:.0}", diff).blue()
+                } else {
+                    format!("{:.0}", diff).red()
+                }
+            );
+        }
+    }
+}
+
 // Picks two characters to fight, returns the indices within the pool
 pub fn matchmake(rng: &mut ThreadRng, pool: &Vec<&mut Chara>, unranked_picks: &mut HashSet<usize>)
 -> Vec<usize> {
@@ -106,21 +276,79 @@ pub fn matchmake(rng: &mut ThreadRng, pool: &Vec<&mut Chara>, unranked_picks: &m
     pair_id
 }
 
-// Parses line for tags (series, stages) and flags (pc98, notgirl, and nameless)
-// Consumes line and returns a vec of tags and array of bool flags
+// Removes a character (by global ID) from the session pool, keeping
+// the indices and the picks of unranked characters in sync
+pub fn kick(pool: &mut Vec<&mut Chara>, indices: &mut Vec<usize>, unranked_picks: &mut HashSet<usize>, global_id: usize) {
+    let Some(pos) = indices.iter().position(|a| *a == global_id) else {
+        return;
+    };
+    pool.remove(pos);
+    indices.remove(pos);
+    // picks are positions in the pool, everyone after pos moves up by one
+    *unranked_picks = unranked_picks.iter()
+        .filter(|n| **n != pos)
+        .map(|n| if *n > pos { n - 1 } else { *n })
+        .collect();
+}
+
+// Splits the arguments of `list` and friends into a number, the filter tags,
+// and whatever is left as a name filter
+pub fn split_args(line: &str)
+-> (Option<usize>, String, String) {
+    let mut number = None;
+    let mut name_filter = "".to_owned();
+    let mut tags_filter = "".to_owned();
+    for token in line.trim().split(" ").skip(1) {
+        if token.parse::<usize>().is_ok() {
+            number = Some(token.parse().unwrap());  // is a number
+        } else {
+            // check if it is a tag
+            let token_unsigned = if token.starts_with("-") {
+                &token[1..]
+            } else {
+                &token[..]
+            };
+            match Tags::from_str(token_unsigned) {
+                Ok(_) => {
+                    // is a tag, add it to filter
+                    tags_filter.push_str(&(token.to_string() + " "));
+                },
+                Err(_) => {
+                    if pack::flag_rule(token).is_some() || meta::rule(token).is_some() {
+                        tags_filter.push_str(&(token.to_string() + " "));
+                    } else {
+                        // is not a flag, treat as name
+                        name_filter.push_str(&(token.to_string() + " "));
+                    }
+                },
+            }
+        }
+    }
+    (number, tags_filter, name_filter)
+}
+
+// What a filter does with each flag of the pack
+type FlagRules = Vec<(String, Rule)>;
+// Metadata a filter asks for, like species=youkai: the key, the value, and in or out
+type MetaRules = Vec<(String, String, bool)>;
+
+// Parses line for tags (series, stages), the pack's flags (pc98, notgirl, and nameless) and metadata
+// Consumes line and returns a vec of tags, what to do with each flag, and the metadata rules
 pub fn parse_filter(line: String)
--> (Vec<(Tags, bool)>, [bool; 3]) {
+-> (Vec<(Tags, bool)>, FlagRules, MetaRules) {
     let mut tags: Vec<(Tags, bool)> = Vec::new();
-    // Default: no pc98, no non-girls, include nameless
-    let mut flags: [bool; 3] = [false, false, true];
+    let mut facts: MetaRules = Vec::new();
+    // Default: whatever the pack says (no pc98, no non-girls, include nameless)
+    let mut flags: FlagRules = pack::flags().into_iter()
+        .map(|a| (a.key, if a.include { Rule::In } else { Rule::Out }))
+        .collect();
     let linev: Vec<&str> = line.split(" ").into_iter().collect();
 
     // remove unrecognised tokens
     let verify = |a: &&str| {
         *a == ""
-        ||  a.contains("pc98")
-        ||  a.contains("notgirl")
-        ||  a.contains("namel")
+        ||  pack::flag_rule(a.trim()).is_some()
+        ||  meta::rule(a.trim()).is_some()
         ||  if a.starts_with("-") {
                 Tags::from_str(&a.trim()[1..]).is_ok()
             } else {
@@ -154,34 +382,28 @@ pub fn parse_filter(line: String)
                 tags.push((t, !is_excl));
             },
             Err(_) => {
-                match token {
-                    t if t.contains("pc98")     => {
-                        if t.starts_with("-") {
-                            println!("Note: PC-98 duplicates are excluded by default.");
-                        } else {
-                            flags[0] = true;
-                        }
-                    },
-                    t if t.contains("notgirl")  => {
-                        if t.starts_with("-") {
-                            println!("Note: Non-girls are excluded by default.");
-                        } else {
-                            flags[1] = true;
-                        }
-                    },
-                    t if t.contains("namel")   => {
-                        if t.starts_with("-") {
-                            flags[2] = false;
-                        } else {
-                            println!("Note: Nameless characters are included by default.");
+                if let Some((key, value, include)) = meta::rule(token) {
+                    println!("Filter: {} {} {}",
+                        if include { "Only".blue() } else { "Excluding".red() },
+                        meta::title(&key).unwrap_or(""),
+                        value.replace('_', " ").bold()
+                    );
+                    facts.push((key, value, include));
+                } else if let Some((flag, rule)) = pack::flag_rule(token) {
+                    let slot = flags.iter_mut().find(|a| a.0 == flag.key).unwrap();
+                    if rule == slot.1 {
+                        println!("Note: {} are {} by default.", flag.name, if flag.include { "included" } else { "excluded" });
+                    } else {
+                        if rule == Rule::Only {
+                            println!("Filter: {} {}", "Only".blue(), flag.name.bold());
                         }
-                    },
-                    _ => { /* shouldn't happen? */ },
+                        slot.1 = rule;
+                    }
                 }
             },
         }
     }
-    (tags, flags)
+    (tags, flags, facts)
 }
 
 // Takes a line of user filters and generates the pool of contestants, consumes line and
@@ -190,14 +412,18 @@ pub fn parse_filter(line: String)
 pub fn bouncer(line: String, touhous: &mut Vec<Chara>)
 -> (Vec<&mut Chara>, Vec<usize>) {
     // filter by tags
-    let (tags, flags): (Vec<(Tags, bool)>, [bool; 3]) = parse_filter(line);
+    let (tags, flags, facts): (Vec<(Tags, bool)>, FlagRules, MetaRules) = parse_filter(line);
     let (mut filtered, mut indices): (Vec<&mut Chara>,_) = stats::filter_group_mut(tags, touhous);
     // filter by flags (pc98, nongirls, nameless)
-    // flags[]: remove if 0
+    // retired ones are out of the list, they don't battle anymore
     let flag_filter = |a: &&mut Chara| {
-        !(!flags[0] && a.is_pc98()
-            || !flags[1] && a.is_not_girl()
-            || !flags[2] && a.is_nameless())
+        !a.retired
+        && flags.iter().all(|(key, rule)| match rule {
+            Rule::In => true,
+            Rule::Out => !a.has_flag(key),
+            Rule::Only => a.has_flag(key),
+        })
+        && facts.iter().all(|(key, value, include)| meta::matches(a, key, value) == *include)
     };
     // we want to filter both filtered() and indices() at once
     let to_remove: Vec<usize> = filtered.iter().enumerate()
@@ -1,7 +1,9 @@
 // Tohorank: statistics helpers
 
-use crate::{Chara, Tags};
+use crate::{INCLUSIVE, Chara, Tags};
 use colored::Colorize;
+use rand::Rng;
+use rand::rngs::ThreadRng;
 
 // Get the ranking of a character in the pool
 pub fn rank_in_group(touhou: &Chara, pool: &Vec<&Chara>)
@@ -16,83 +18,146 @@ pub fn rank_in_group(touhou: &Chara, pool: &Vec<&Chara>)
     (rank, pool.iter().filter(|t| !t.dont_know()).count())
 }
 
-// Filter characters in pool by tags
-// Every tag is either inclusive (true) or exclusive (false)
-pub fn filter_group<'a>(tags: Vec<(Tags, bool)>, pool: &'a Vec<Chara>)
--> Vec<&'a Chara> {
-    let mut filtered: Vec<&Chara> = Vec::new();
-    // split the two types of tags
-    let (series_t, stages_t): (Vec<_>, Vec<_>) = tags.into_iter().
-        partition(|a| a.0.is_series_tag());
+// Aggregate numbers of one group, for `groups`
+// the group is a tag, or a value of some metadata
+pub struct GroupStats<G> {
+    pub group: G,
+    pub size: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub top: usize,         // members in the overall top 10
+    pub favorite: f64,      // share of members who are #1 in any of their groups (★)
+    pub devi: f64,          // average 1.96·RD
+}
 
-    // first round: series tags
-    for th in pool.iter() {
-        // bit long innit
-        let no_specified_incl_tags = series_t.iter().filter(|a| a.1).collect::<Vec<_>>().is_empty();
-        let has_any_incl_tags = series_t.iter().filter(|a| a.1).any(|tag| th.has_tag(&tag.0));
-        let has_no_excl_tags = series_t.iter().filter(|a| !a.1).all(|tag| !th.has_tag(&tag.0));
+// True if the character is #1 in any of their groups (the ★ in `list`)
+pub fn is_favorite(touhou: &Chara, pool: &Vec<Chara>)
+-> bool {
+    touhou.tags().iter().any(|tag| {
+        let group = filter_group(vec![(tag.clone(), INCLUSIVE)], pool);
+        rank_in_group(touhou, &group).0 == 1
+    })
+}
 
-        if series_t.is_empty()
-           || (no_specified_incl_tags || has_any_incl_tags) && has_no_excl_tags
-        {
-            filtered.push(th);
-        }
-    }
-    // second round: stages tags
-    let stage_filter = |th: &&Chara| {
-        let no_specified_incl_tags = stages_t.iter().filter(|a| a.1).collect::<Vec<_>>().is_empty();
-        let has_any_incl_tags = stages_t.iter().filter(|a| a.1).any(|tag| th.has_tag(&tag.0));
-        let has_no_excl_tags = stages_t.iter().filter(|a| !a.1).all(|tag| !th.has_tag(&tag.0));
+// Aggregates a group, None if nobody (known) is in it
+pub fn group_stats(tag: Tags, pool: &Vec<Chara>, favorites: &[bool])
+-> Option<GroupStats<Tags>> {
+    let member = |th: &Chara| th.has_tag(&tag);
+    aggregate(tag.clone(), member, pool, favorites)
+}
 
-        stages_t.is_empty()
-           || (no_specified_incl_tags || has_any_incl_tags) && has_no_excl_tags
+// Aggregates everyone `member` picks, None if nobody (known) is picked
+pub fn aggregate<G>(group: G, member: impl Fn(&Chara) -> bool, pool: &Vec<Chara>, favorites: &[bool])
+-> Option<GroupStats<G>> {
+    let everyone = filter_group(vec![], pool);
+    let members: Vec<(usize, &Chara)> = pool.iter()
+        .enumerate()
+        .filter(|(_, th)| member(th) && !th.dont_know())
+        .collect();
+    if members.is_empty() {
+        return None;
+    }
+    let size = members.len();
+    let mut rates: Vec<f64> = members.iter().map(|(_, th)| th.rank.rate).collect();
+    rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    #[allow(clippy::manual_is_multiple_of)]
+    let median = if size % 2 == 0 {
+        (rates[size / 2 - 1] + rates[size / 2]) / 2.0
+    } else {
+        rates[size / 2]
     };
-    filtered.retain(stage_filter);
-    // final result
-    filtered
+    Some(GroupStats {
+        group,
+        size,
+        mean: rates.iter().sum::<f64>() / size as f64,
+        median,
+        top: members.iter().filter(|(_, th)| rank_in_group(th, &everyone).0 <= 10).count(),
+        favorite: members.iter().filter(|(id, _)| favorites[*id]).count() as f64 / size as f64,
+        devi: members.iter().map(|(_, th)| th.rank.devi * 1.96).sum::<f64>() / size as f64,
+    })
 }
 
-// Same as above but returns mutable references, for sorting
-// also returns a mapping of filtered indices to unfiltered indices
-pub fn filter_group_mut<'a>(tags: Vec<(Tags, bool)>, pool: &'a mut Vec<Chara>)
--> (Vec<&'a mut Chara>, Vec<usize>) {
-    let mut filtered: Vec<&mut Chara> = Vec::new();
-    let mut indices: Vec<usize> = Vec::with_capacity(filtered.len());
-    let (series_t, stages_t): (Vec<_>, Vec<_>) = tags.into_iter().
-        partition(|a| a.0.is_series_tag());
-    for (id, th) in pool.iter_mut().enumerate() {
-        let no_specified_incl_tags = series_t.iter().filter(|a| a.1).collect::<Vec<_>>().is_empty();
-        let has_any_incl_tags = series_t.iter().filter(|a| a.1).any(|tag| th.has_tag(&tag.0));
-        let has_no_excl_tags = series_t.iter().filter(|a| !a.1).all(|tag| !th.has_tag(&tag.0));
+// Standard normal CDF, using the Abramowitz-Stegun approximation of erf
+pub fn normal_cdf(x: f64)
+-> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * z);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-z * z).exp();
+    if x >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
 
-        if series_t.is_empty()
-           || (no_specified_incl_tags || has_any_incl_tags) && has_no_excl_tags
-        {
-            filtered.push(th);
-            indices.push(id);
+// Samples everyone's rating from N(rate, RD) and ranks them, rounds times
+// Returns how often each character (in pool order) landed on each rank, counts[rank - 1]
+pub fn simulate_ranks(pool: &[&Chara], rounds: usize, rng: &mut ThreadRng)
+-> Vec<Vec<usize>> {
+    let mut counts: Vec<Vec<usize>> = vec![vec![0; pool.len()]; pool.len()];
+    let mut draws: Vec<(f64, usize)> = Vec::with_capacity(pool.len());
+    for _ in 0..rounds {
+        draws.clear();
+        for (n, th) in pool.iter().enumerate() {
+            // Box-Muller
+            let (u1, u2): (f64, f64) = (1.0 - rng.gen::<f64>(), rng.gen());
+            let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+            draws.push((th.rank.rate + z * th.rank.devi, n));
+        }
+        draws.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        for (rank, (_, n)) in draws.iter().enumerate() {
+            counts[*n][rank] += 1;
         }
     }
-    let stage_filter = |th: &&mut Chara| {
-        let no_specified_incl_tags = stages_t.iter().filter(|a| a.1).collect::<Vec<_>>().is_empty();
-        let has_any_incl_tags = stages_t.iter().filter(|a| a.1).any(|tag| th.has_tag(&tag.0));
-        let has_no_excl_tags = stages_t.iter().filter(|a| !a.1).all(|tag| !th.has_tag(&tag.0));
+    counts
+}
 
-        stages_t.is_empty()
-           || (no_specified_incl_tags || has_any_incl_tags) && has_no_excl_tags
-    };
-    // we want to filter both filtered() and indices() at once, so retain() doesn't cut it
-    // or maybe it does and I'm just bad.
-    let to_remove: Vec<usize> = filtered.iter().enumerate()
-        .filter(|(_, &ref th)| !stage_filter(&th) /* remove if false */)
-        .map(|(id, _)| id)
-        .collect();
-    let mut offset = 0;
-    for id in to_remove {
-        filtered.remove(id - offset);
-        indices.remove(id - offset);
-        offset += 1; // as we remove the index is shifted
+// Split the filter tags by category, each category is its own round
+fn rounds(tags: Vec<(Tags, bool)>)
+-> Vec<Vec<(Tags, bool)>> {
+    let mut rounds: Vec<(String, Vec<(Tags, bool)>)> = Vec::new();
+    for tag in tags {
+        let category = tag.0.category();
+        match rounds.iter_mut().find(|a| a.0 == category) {
+            Some(round) => { round.1.push(tag); },
+            None => { rounds.push((category, vec![tag])); },
+        }
     }
-    (filtered, indices)
+    rounds.into_iter().map(|a| a.1).collect()
+}
+
+// A character passes a round if they have any of the included tags (if there are any)
+// and none of the excluded ones, and they have to pass every round
+fn passes(th: &Chara, rounds: &[Vec<(Tags, bool)>])
+-> bool {
+    rounds.iter().all(|round| {
+        let no_specified_incl_tags = round.iter().all(|a| !a.1);
+        let has_any_incl_tags = round.iter().filter(|a| a.1).any(|tag| th.has_tag(&tag.0));
+        let has_no_excl_tags = round.iter().filter(|a| !a.1).all(|tag| !th.has_tag(&tag.0));
+        (no_specified_incl_tags || has_any_incl_tags) && has_no_excl_tags
+    })
+}
+
+// Filter characters in pool by tags
+// Every tag is either inclusive (true) or exclusive (false)
+// so `in gfw -st4` is anyone from IN or GFW, but not from stage 4
+pub fn filter_group<'a>(tags: Vec<(Tags, bool)>, pool: &'a Vec<Chara>)
+-> Vec<&'a Chara> {
+    let rounds = rounds(tags);
+    pool.iter().filter(|th| passes(th, &rounds)).collect()
+}
+
+// Same as above but returns mutable references, for sorting
+// also returns a mapping of filtered indices to unfiltered indices
+pub fn filter_group_mut<'a>(tags: Vec<(Tags, bool)>, pool: &'a mut Vec<Chara>)
+-> (Vec<&'a mut Chara>, Vec<usize>) {
+    let rounds = rounds(tags);
+    pool.iter_mut()
+        .enumerate()
+        .filter(|(_, th)| passes(th, &rounds))
+        .map(|(id, th)| (th, id))
+        .unzip()
 }
 
 // Get slice of ranking around the character in a group
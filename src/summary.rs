@@ -0,0 +1,160 @@
+// Tohorank: end of session summary
+
+use crate::{DEVIATION_BAR, Chara, Match, Session, data, glicko, stats};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use chrono::Local;
+use colored::Colorize;
+
+// how many entries to show in each part of the summary
+const SUMMARY_LEN: usize = 5;
+
+// Overall rank of everyone (by global ID), hidden characters get 0
+fn overall_ranks(touhous: &Vec<Chara>)
+-> Vec<usize> {
+    let everyone = stats::filter_group(vec![], touhous);
+    touhous.iter()
+        .map(|th| if th.dont_know() { 0 } else { stats::rank_in_group(th, &everyone).0 })
+        .collect()
+}
+
+// Prints the summary of a session and saves it to the match log
+// before: the characters before glicko::calc(), after: the characters after
+pub fn session(before: &Vec<Chara>, after: &Vec<Chara>, records: &[Match], elapsed: Duration, data_path: &Path) {
+    if records.is_empty() {
+        return;
+    }
+    // the summary text that goes to the log, headers are bolded on screen only
+    let mut text: Vec<String> = Vec::new();
+    let header = |text: &mut Vec<String>, title: &str| {
+        println!("\n==> {}", title.bold());
+        text.push(format!("\n==> {}", title));
+    };
+    let line = |text: &mut Vec<String>, entry: String| {
+        println!("{}", entry);
+        text.push(entry);
+    };
+
+    println!("\n{}", "======== Session Summary ========".blue());
+
+    // Session stats
+    header(&mut text, "SESSION");
+    let draws = records.iter().filter(|a| a.res == 0.5).count();
+    let dislikes = records.iter().filter(|a| a.res == 2.0).count();
+    let faces: HashSet<usize> = records.iter().flat_map(|a| [a.one, a.two]).collect();
+    line(&mut text, format!("    {} {} between {} characters",
+        records.len(),
+        if records.len() == 1 { "battle" } else { "battles" },
+        faces.len()
+    ));
+    line(&mut text, format!("    {} draws, {} disliked both", draws, dislikes));
+    line(&mut text, format!("    Took {}m {}s ({:.1}s per battle)",
+        elapsed.as_secs() / 60,
+        elapsed.as_secs() % 60,
+        elapsed.as_secs_f64() / records.len() as f64
+    ));
+
+    // Upsets: the picks the old ratings expected the least
+    let mut upsets: Vec<(f64, usize, usize)> = records.iter()
+        .filter(|a| a.res == 1.0 || a.res == 0.0)
+        .map(|a| {
+            let (winner, loser) = if a.res == 1.0 { (a.one, a.two) } else { (a.two, a.one) };
+            let (mut mu, mut phi) = (before[winner].rank.rate, before[winner].rank.devi);
+            let (mut muj, mut phij) = (before[loser].rank.rate, before[loser].rank.devi);
+            glicko::glicko_two_scale(&mut mu, &mut phi);
+            glicko::glicko_two_scale(&mut muj, &mut phij);
+            (glicko::e(&mu, &muj, &phij), winner, loser)
+        })
+        .filter(|a| a.0 < 0.5)
+        .collect();
+    upsets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    if !upsets.is_empty() {
+        header(&mut text, "UPSETS");
+        for (expected, winner, loser) in upsets.iter().take(SUMMARY_LEN) {
+            line(&mut text, format!("    {} ({:.0}) over {} ({:.0}), {:.0}% expected",
+                before[*winner].name,
+                before[*winner].rank.rate,
+                before[*loser].name,
+                before[*loser].rank.rate,
+                expected * 100.0
+            ));
+        }
+    }
+
+    // New peaks
+    let peaks: Vec<String> = faces.iter()
+        .filter_map(|id| {
+            let (old, new) = (&before[*id].hist, &after[*id].hist);
+            let mut got = Vec::new();
+            if new.peak_rank.as_ref().map(|a| a.0) != old.peak_rank.as_ref().map(|a| a.0) {
+                got.push(format!("rank #{}", new.peak_rank.as_ref().unwrap().0));
+            }
+            if new.peak_rate.as_ref().map(|a| a.0) != old.peak_rate.as_ref().map(|a| a.0) {
+                got.push(format!("rating {:.0}", new.peak_rate.as_ref().unwrap().0));
+            }
+            if got.is_empty() {
+                None
+            } else {
+                Some(format!("    {:<26}{}", after[*id].name, got.join(", ")))
+            }
+        })
+        .collect();
+    if !peaks.is_empty() {
+        header(&mut text, "NEW PEAKS");
+        for entry in peaks {
+            line(&mut text, entry);
+        }
+    }
+
+    // Rank movements
+    let (ranks_before, ranks_after) = (overall_ranks(before), overall_ranks(after));
+    let mut moves: Vec<(isize, usize)> = faces.iter()
+        .filter(|id| ranks_before[**id] != 0 && ranks_after[**id] != 0)
+        .map(|id| (ranks_before[*id] as isize - ranks_after[*id] as isize, *id))
+        .filter(|a| a.0 != 0)
+        .collect();
+    moves.sort_by(|a, b| b.0.abs().cmp(&a.0.abs()).then(b.0.cmp(&a.0)));
+    if !moves.is_empty() {
+        header(&mut text, "RANK MOVEMENTS");
+        for (diff, id) in moves.iter().take(SUMMARY_LEN * 2) {
+            let entry = format!("    {:<26}#{} -> #{} ({}{})",
+                after[*id].name,
+                ranks_before[*id],
+                ranks_after[*id],
+                if *diff > 0 { "+" } else { "" },
+                diff
+            );
+            if *diff > 0 {
+                println!("{}", entry.blue());
+            } else {
+                println!("{}", entry.red());
+            }
+            text.push(entry);
+        }
+    }
+
+    // Settled: no longer high deviation
+    let settled: Vec<&str> = faces.iter()
+        .filter(|id| before[**id].rank.devi > DEVIATION_BAR && after[**id].rank.devi <= DEVIATION_BAR)
+        .map(|id| after[*id].name.as_str())
+        .collect();
+    if !settled.is_empty() {
+        header(&mut text, "SETTLED");
+        line(&mut text, format!("    {} no longer {} high uncertainty:",
+            settled.len(),
+            if settled.len() == 1 { "has" } else { "have" }
+        ));
+        for name in settled {
+            line(&mut text, format!("    - {}", name));
+        }
+    }
+    println!();
+
+    data::append_log(Session {
+        time: Local::now().format("%d %b, %Y %H:%M").to_string(),
+        secs: elapsed.as_secs(),
+        records: records.to_vec(),
+        summary: text.join("\n"),
+    }, data_path);
+}
@@ -0,0 +1,248 @@
+// Tohorank: tier lists
+// Splits the ranking into S/A/B/C/D tiers, by fixed ratings, quantiles or natural breaks.
+
+use crate::{Chara, sort};
+use colored::{ColoredString, Colorize};
+
+pub const TIERS: [&str; 5] = ["S", "A", "B", "C", "D"];
+
+// Default cutoffs
+const FIXED: [f64; 4] = [1800.0, 1650.0, 1500.0, 1350.0];  // lowest rating in S, A, B, C
+const QUANTILE: [f64; 5] = [10.0, 20.0, 40.0, 20.0, 10.0]; // % of characters in S, A, B, C, D
+
+// How to draw the lines between tiers
+pub enum Method {
+    Fixed(Vec<f64>),    // lowest rating of each tier but the last
+    Quantile(Vec<f64>), // share of each tier in %
+    Breaks,             // natural breaks, weighted k-means
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Fixed(_) => "fixed ratings",
+            Method::Quantile(_) => "quantiles",
+            Method::Breaks => "natural breaks",
+        }
+    }
+}
+
+// Parses `tiers [fixed|quantile|breaks] [numbers...] [filters]`
+// Returns the method and the filter tags for sort::bouncer()
+pub fn parse_args(line: &str)
+-> (Method, String) {
+    let mut method = "";
+    let mut numbers: Vec<f64> = Vec::new();
+    let mut rest = String::from("tiers");
+    for token in line.trim().split(' ').skip(1) {
+        if token.starts_with("fix") || token.starts_with("quant") || token.starts_with("break") || token == "jenks" {
+            method = token;
+        } else if let Ok(number) = token.parse::<f64>() {
+            numbers.push(number);
+        } else {
+            rest.push(' ');
+            rest.push_str(token);
+        }
+    }
+    let (_, tags_filter, _) = sort::split_args(&rest);
+    let method = if method.starts_with("fix") {
+        if numbers.is_empty() {
+            Method::Fixed(FIXED.to_vec())
+        } else {
+            numbers.sort_by(|a, b| b.partial_cmp(a).unwrap());
+            numbers.truncate(TIERS.len() - 1);
+            Method::Fixed(numbers)
+        }
+    } else if method.starts_with("quant") {
+        if numbers.is_empty() {
+            Method::Quantile(QUANTILE.to_vec())
+        } else {
+            numbers.truncate(TIERS.len());
+            Method::Quantile(numbers)
+        }
+    } else {
+        Method::Breaks
+    };
+    (method, tags_filter)
+}
+
+// Gives each character (sorted by rating, highest first) a tier, 0 is S
+pub fn assign(touhous: &[&Chara], method: &Method)
+-> Vec<usize> {
+    match method {
+        Method::Fixed(cutoffs) => touhous.iter()
+            .map(|th| cutoffs.iter().take_while(|c| th.rank.rate < **c).count())
+            .collect(),
+        Method::Quantile(shares) => {
+            let total: f64 = shares.iter().sum();
+            let mut tiers = Vec::with_capacity(touhous.len());
+            let mut bound = 0.0;
+            for (tier, share) in shares.iter().enumerate() {
+                bound += share / total * touhous.len() as f64;
+                while (tiers.len() as f64) < bound.round() {
+                    tiers.push(tier);
+                }
+            }
+            while tiers.len() < touhous.len() {
+                tiers.push(shares.len().max(1) - 1);
+            }
+            tiers.truncate(touhous.len());
+            // ties share the better tier
+            for n in 1..touhous.len() {
+                if touhous[n].rank.rate == touhous[n - 1].rank.rate {
+                    tiers[n] = tiers[n - 1];
+                }
+            }
+            tiers
+        },
+        Method::Breaks => breaks(touhous),
+    }
+}
+
+// Natural breaks: 1D k-means on the ratings, weighted by 1/RD² so
+// the uncertain ones don't pull the tiers around as much
+fn breaks(touhous: &[&Chara])
+-> Vec<usize> {
+    let k = TIERS.len().min(touhous.len());
+    let rates: Vec<f64> = touhous.iter().map(|th| th.rank.rate).collect();
+    let weights: Vec<f64> = touhous.iter().map(|th| th.rank.devi.powi(-2)).collect();
+    // start from evenly spaced quantiles
+    let mut centers: Vec<f64> = (0..k)
+        .map(|n| rates[(n * (rates.len() - 1)) / (k - 1).max(1)])
+        .collect();
+    let mut tiers: Vec<usize> = vec![0; rates.len()];
+    for _ in 0..100 {
+        // assign to the nearest center
+        let mut changed = false;
+        for (n, rate) in rates.iter().enumerate() {
+            let nearest = centers.iter()
+                .enumerate()
+                .min_by(|a, b| (a.1 - rate).abs().partial_cmp(&(b.1 - rate).abs()).unwrap())
+                .unwrap().0;
+            if tiers[n] != nearest {
+                tiers[n] = nearest;
+                changed = true;
+            }
+        }
+        // move the centers
+        for (tier, center) in centers.iter_mut().enumerate() {
+            let (sum, weight) = rates.iter().zip(weights.iter()).zip(tiers.iter())
+                .filter(|(_, t)| **t == tier)
+                .fold((0.0, 0.0), |acc, ((r, w), _)| (acc.0 + r * w, acc.1 + w));
+            if weight > 0.0 {
+                *center = sum / weight;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    // renumber so the highest center is S, empty tiers are skipped
+    let mut order: Vec<usize> = (0..k).filter(|c| tiers.contains(c)).collect();
+    order.sort_by(|a, b| centers[*b].partial_cmp(&centers[*a]).unwrap());
+    tiers.iter().map(|t| order.iter().position(|o| o == t).unwrap()).collect()
+}
+
+// The color of each tier
+pub fn paint(tier: usize, text: &str)
+-> ColoredString {
+    match tier {
+        0 => text.truecolor(245, 212, 95),
+        1 => text.truecolor(240, 140, 95),
+        2 => text.truecolor(180, 245, 212),
+        3 => text.truecolor(140, 180, 250),
+        _ => text.truecolor(182, 185, 191),
+    }
+}
+
+// Prints the tier list
+pub fn show(mut touhous: Vec<&Chara>, method: &Method) {
+    touhous.retain(|th| !th.dont_know());
+    touhous.sort_by(|a, b| b.rank.rate.partial_cmp(&a.rank.rate).unwrap());
+    let tiers = assign(&touhous, method);
+
+    println!("==> {} ({})", "TIER LIST".bold(), method.name());
+    for (tier, label) in TIERS.iter().enumerate() {
+        let members: Vec<(usize, &&Chara)> = touhous.iter()
+            .enumerate()
+            .filter(|(n, _)| tiers[*n] == tier)
+            .collect();
+        if members.is_empty() {
+            println!("{}", paint(tier, &format!(" {} |", label)).bold());
+            continue;
+        }
+        // is their rating interval reaching into another tier?
+        let lowest = members.last().unwrap().1.rank.rate;
+        let highest = members.first().unwrap().1.rank.rate;
+        let names: Vec<String> = members.iter()
+            .map(|(_, th)| {
+                let unsure = th.rank.rate - th.rank.devi * 1.96 < lowest && tier < TIERS.len() - 1
+                    || th.rank.rate + th.rank.devi * 1.96 > highest && tier > 0;
+                format!("{}{}", th.name, if unsure { "?" } else { "" })
+            })
+            .collect();
+        // wrap the names at about 80 columns
+        let mut row = String::new();
+        let mut first = true;
+        for name in names {
+            if !row.is_empty() && row.len() + name.len() > 72 {
+                println!("{} {}", paint(tier, &format!(" {} |", if first { label } else { " " })).bold(), paint(tier, &row));
+                row.clear();
+                first = false;
+            }
+            if !row.is_empty() {
+                row.push_str(", ");
+            }
+            row.push_str(&name);
+        }
+        println!("{} {}", paint(tier, &format!(" {} |", if first { label } else { " " })).bold(), paint(tier, &row));
+        println!("     {}", format!("({} {}, {:.0} ~ {:.0})",
+            members.len(),
+            if members.len() == 1 { "character" } else { "characters" },
+            lowest,
+            highest
+        ).truecolor(182, 185, 191));
+    }
+    println!("\nⓘ ? marks a character whose rating interval reaches into another tier.");
+    println!("ⓘ Methods: `tiers fixed [ratings]`, `tiers quantile [%s]`, `tiers breaks`.\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(rates: &[f64])
+    -> Vec<Chara> {
+        rates.iter().enumerate().map(|(n, rate)| Chara::rated(&n.to_string(), *rate, 50.0)).collect()
+    }
+
+    #[test]
+    fn fixed_cutoffs() {
+        let touhous = pool(&[1900.0, 1700.0, 1650.0, 1400.0]);
+        let refs: Vec<&Chara> = touhous.iter().collect();
+        assert_eq!(assign(&refs, &Method::Fixed(vec![1800.0, 1600.0])), vec![0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn quantiles_and_ties() {
+        let touhous = pool(&[2000.0, 1900.0, 1800.0, 1800.0, 1700.0, 1600.0, 1500.0, 1400.0, 1300.0, 1200.0]);
+        let refs: Vec<&Chara> = touhous.iter().collect();
+        // 20% / 30% / 50%, the tie at 1800 goes up with the better one
+        assert_eq!(assign(&refs, &Method::Quantile(vec![20.0, 30.0, 50.0])), vec![0, 0, 1, 1, 1, 2, 2, 2, 2, 2]);
+        let tiers = assign(&refs, &Method::Quantile(vec![10.0, 10.0]));
+        assert_eq!(tiers.len(), refs.len());
+    }
+
+    #[test]
+    fn breaks_find_the_gaps() {
+        let touhous = pool(&[2000.0, 1995.0, 1500.0, 1495.0, 1000.0, 995.0]);
+        let refs: Vec<&Chara> = touhous.iter().collect();
+        let tiers = assign(&refs, &Method::Breaks);
+        // five tiers for six, so a pair is split, but never across a gap
+        assert_eq!(tiers[0], 0);
+        assert!(tiers.windows(2).all(|a| a[0] <= a[1]));
+        assert_ne!(tiers[1], tiers[2]);
+        assert_ne!(tiers[3], tiers[4]);
+        assert_eq!(*tiers.iter().max().unwrap(), TIERS.len() - 1);
+    }
+}